@@ -0,0 +1,580 @@
+use autonetconf::ip::{IPType, IPv4, Subnet, IP};
+use autonetconf::lint;
+use autonetconf::output::Output;
+use autonetconf::parse::Conf;
+use autonetconf::schema::{self, Param, ParamKind};
+use autonetconf::session::Credentials;
+use autonetconf::sim::Device;
+use autonetconf::values::{self, Format, KeyReport};
+use autonetconf::wizard::Wizard;
+
+pub const USAGE: &str = "\
+usage: autonetconf <command> <template> [options]
+       autonetconf simulate [options]
+       autonetconf plan <block> <name>=<hosts>... [options]
+
+commands:
+    render      fill in the template and send the compiled config somewhere
+    describe    list the parameters the template accepts
+    lint        check the template for likely mistakes, e.g. a `! for` that isn't closed
+    wizard      ask for each parameter of the template, then send the compiled config
+                somewhere (takes the same output options as render)
+    simulate    run a fake IOS device on a tcp port, to try `--output telnet` against
+    plan        split an ipv4 block into subnets for the given number of hosts each,
+                largest first (VLSM), e.g. `plan 192.168.0.0/24 lan=120 guest=50 wan=2`
+
+render options:
+    --values <file>                 read parameters from a .toml, .json or .yaml file
+    --set <key>=<value>             set a parameter
+    --present <key>                 mark a single-option parameter (e.g. `?nodns{no}`) as present
+    --list [<key>:<n>/...]<key> <k>=<v>[,<k>=<v>]
+                                    append an entry to a `! for $key` list, which can be in
+                                    an existing list entry (entries start at 0)
+    --item <key>:<n>[/<key>:<n>] <k>=<v>
+                                    set a value in an existing list entry
+    -c, --check                     only check the values, reporting every problem
+    -o, --output <stdout|clip|minicom|file|serial|telnet|ssh>
+                                    where to send the compiled config (default: stdout)
+    -f, --file <path>               file to write to, implies `--output file`
+    --device <path>                 serial device for `--output serial`, e.g. /dev/ttyUSB0
+    --baud <rate>                   serial baud rate (default: 9600)
+    --host <host>[:<port>]          device for `--output telnet` or `--output ssh`,
+                                    an IPv6 address with a port goes in brackets, e.g. [::1]:23
+                                    (the login options below also apply to `--output serial`)
+    --username <name>               login username (default: $username from the template)
+    --password <password>           login password (default: $user.password or $password)
+    --secret <secret>               enable secret (default: $secret)
+
+describe options:
+    --json                          print a JSON schema for values files instead of a table
+
+simulate options:
+    --port <port>                   port to listen on, on localhost (default: 2323)
+    --hostname <name>               name in the prompt (default: Router)
+    --username <name> --password <password>
+                                    ask for a login, or only a line password without a username
+    --secret <secret>               ask for a secret on `enable`
+
+plan options:
+    --format <table|toml|json|yaml> print the subnets as a table (default), or as a values
+                                    file for a `! for $subnet` list (name, hosts, network,
+                                    gateway)
+
+    -h, --help                      print this message";
+
+/// Operations applied to the template, in the order they were given
+pub enum Op {
+    Values(String),
+    Set(String, String),
+    Present(String),
+    List(Vec<(String, usize)>, String, Vec<(String, String)>),
+    Item(Vec<(String, usize)>, String, String),
+}
+
+pub enum Command {
+    Render {
+        template: String,
+        ops: Vec<Op>,
+        output: Output,
+        check: bool,
+    },
+    Describe {
+        template: String,
+        json: bool,
+    },
+    Wizard {
+        template: String,
+        output: Output,
+    },
+    Lint {
+        template: String,
+    },
+    Simulate {
+        port: u16,
+        device: Device,
+    },
+    Plan {
+        block: IPv4,
+        needs: Vec<(String, u32)>,
+        /// `None` prints a table
+        format: Option<Format>,
+    },
+    Help,
+}
+
+fn key_value(s: &str) -> Result<(String, String), String> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(k), Some(v)) if !k.is_empty() => Ok((k.to_string(), v.to_string())),
+        _ => Err(format!("expected `<key>=<value>`, found `{}`", s)),
+    }
+}
+
+/// Options choosing where the compiled config goes, shared by render and wizard
+#[derive(Default)]
+struct OutputArgs {
+    output: Option<String>,
+    file: Option<String>,
+    device: Option<String>,
+    baud: Option<String>,
+    host: Option<String>,
+    login: Credentials,
+}
+
+impl OutputArgs {
+    /// Takes the option if it's an output option, returning whether it was
+    fn arg(
+        &mut self,
+        arg: &str,
+        mut value: impl FnMut(&str) -> Result<String, String>,
+    ) -> Result<bool, String> {
+        match arg {
+            "-o" | "--output" => self.output = Some(value(arg)?),
+            "-f" | "--file" => self.file = Some(value(arg)?),
+            "--device" => self.device = Some(value(arg)?),
+            "--baud" => self.baud = Some(value(arg)?),
+            "--host" => self.host = Some(value(arg)?),
+            "--username" => self.login.username = Some(value(arg)?),
+            "--password" => self.login.password = Some(value(arg)?),
+            "--secret" => self.login.secret = Some(value(arg)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn build(self) -> Result<Output, String> {
+        let output = match (self.output.as_deref(), self.file) {
+            (None, None) | (Some("stdout"), None) => Output::Stdout,
+            (Some("clip"), None) => Output::Clip,
+            (Some("minicom"), None) => Output::Minicom,
+            (None, Some(f)) | (Some("file"), Some(f)) => Output::File(f),
+            (Some("file"), None) => return Err("`--output file` needs `--file <path>`".to_string()),
+            (Some("serial"), None) => Output::Serial {
+                device: self
+                    .device
+                    .ok_or("`--output serial` needs `--device <path>`")?,
+                baud: match self.baud {
+                    Some(b) => b
+                        .parse()
+                        .map_err(|_| format!("`{}` isn't a baud rate", b))?,
+                    None => 9600,
+                },
+                login: self.login,
+            },
+            (Some("telnet"), None) => Output::Telnet {
+                host: self.host.ok_or("`--output telnet` needs `--host <host>`")?,
+                login: self.login,
+            },
+            (Some("ssh"), None) => Output::Ssh {
+                host: self.host.ok_or("`--output ssh` needs `--host <host>`")?,
+                login: self.login,
+            },
+            (Some(o), None) => return Err(format!("unknown output `{}`", o)),
+            (Some(_), Some(_)) => {
+                return Err("`--file` can only be used with `--output file`".to_string())
+            }
+        };
+        Ok(output)
+    }
+}
+
+fn list_path(s: &str) -> Result<Vec<(String, usize)>, String> {
+    s.split('/')
+        .map(|part| {
+            let mut parts = part.rsplitn(2, ':');
+            let num = parts.next().unwrap_or_default();
+            let key = parts
+                .next()
+                .ok_or_else(|| format!("expected `<key>:<n>`, found `{}`", part))?;
+            let num = num
+                .parse()
+                .map_err(|_| format!("`{}` is not a list entry number", num))?;
+            Ok((key.to_string(), num))
+        })
+        .collect()
+}
+
+impl Command {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut args = args.iter();
+        match args.next().map(|s| &s[..]) {
+            Some("render") => Self::parse_render(args),
+            Some("describe") => Self::parse_describe(args),
+            Some("wizard") => Self::parse_wizard(args),
+            Some("lint") => Self::parse_lint(args),
+            Some("simulate") => Self::parse_simulate(args),
+            Some("plan") => Self::parse_plan(args),
+            Some("-h") | Some("--help") | Some("help") => Ok(Self::Help),
+            Some(c) => Err(format!("unknown command `{}`", c)),
+            None => Err("no command given".to_string()),
+        }
+    }
+
+    fn parse_describe(args: std::slice::Iter<String>) -> Result<Self, String> {
+        let mut template = None;
+        let mut json = false;
+        for arg in args {
+            match &arg[..] {
+                "--json" => json = true,
+                "-h" | "--help" => return Ok(Self::Help),
+                a if a.starts_with('-') => return Err(format!("unknown option `{}`", a)),
+                a => {
+                    if template.replace(a.to_string()).is_some() {
+                        return Err(format!("unexpected argument `{}`", a));
+                    }
+                }
+            }
+        }
+        Ok(Self::Describe {
+            template: template.ok_or("no template given")?,
+            json,
+        })
+    }
+
+    fn parse_lint(args: std::slice::Iter<String>) -> Result<Self, String> {
+        let mut template = None;
+        for arg in args {
+            match &arg[..] {
+                "-h" | "--help" => return Ok(Self::Help),
+                a if a.starts_with('-') => return Err(format!("unknown option `{}`", a)),
+                a => {
+                    if template.replace(a.to_string()).is_some() {
+                        return Err(format!("unexpected argument `{}`", a));
+                    }
+                }
+            }
+        }
+        Ok(Self::Lint {
+            template: template.ok_or("no template given")?,
+        })
+    }
+
+    fn parse_simulate(mut args: std::slice::Iter<String>) -> Result<Self, String> {
+        let mut port = 2323;
+        let mut hostname = "Router".to_string();
+        let mut login = Credentials::default();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("`{}` expects a value", name))
+            };
+            match &arg[..] {
+                "--port" => {
+                    let p = value(arg)?;
+                    port = p.parse().map_err(|_| format!("`{}` isn't a port", p))?;
+                }
+                "--hostname" => hostname = value(arg)?,
+                "--username" => login.username = Some(value(arg)?),
+                "--password" => login.password = Some(value(arg)?),
+                "--secret" => login.secret = Some(value(arg)?),
+                "-h" | "--help" => return Ok(Self::Help),
+                a => return Err(format!("unknown option `{}`", a)),
+            }
+        }
+        let mut device = Device::new(&hostname);
+        match (login.username, login.password) {
+            (Some(u), Some(p)) => device = device.user(&u, &p),
+            (None, Some(p)) => device = device.password(&p),
+            (Some(_), None) => return Err("`--username` needs `--password`".to_string()),
+            (None, None) => (),
+        }
+        if let Some(s) = login.secret {
+            device = device.secret(&s);
+        }
+        Ok(Self::Simulate { port, device })
+    }
+
+    fn parse_plan(mut args: std::slice::Iter<String>) -> Result<Self, String> {
+        let mut block = None;
+        let mut needs = Vec::new();
+        let mut format = None;
+        while let Some(arg) = args.next() {
+            match &arg[..] {
+                "--format" => {
+                    let f = args.next().ok_or("`--format` expects a value")?;
+                    format = match &f[..] {
+                        "table" => None,
+                        "toml" => Some(Format::Toml),
+                        "json" => Some(Format::Json),
+                        "yaml" => Some(Format::Yaml),
+                        f => return Err(format!("unknown plan format `{}`", f)),
+                    };
+                }
+                "-h" | "--help" => return Ok(Self::Help),
+                a if a.starts_with('-') => return Err(format!("unknown option `{}`", a)),
+                a if block.is_none() => {
+                    let ip = IPv4::parse(a).map_err(|e| format!("`{}`: {}", a, e))?;
+                    if ip.ip_type() == IPType::DHCP {
+                        return Err("can't plan subnets of `dhcp`".to_string());
+                    }
+                    block = Some(ip);
+                }
+                a => {
+                    let (name, hosts) = key_value(a)?;
+                    let hosts = hosts
+                        .parse()
+                        .map_err(|_| format!("`{}` is not a number of hosts", hosts))?;
+                    needs.push((name, hosts));
+                }
+            }
+        }
+        if needs.is_empty() {
+            return Err("no subnets given, expected `<name>=<hosts>`".to_string());
+        }
+        Ok(Self::Plan {
+            block: block.ok_or("no block given")?,
+            needs,
+            format,
+        })
+    }
+
+    fn parse_wizard(mut args: std::slice::Iter<String>) -> Result<Self, String> {
+        let mut template = None;
+        let mut output = OutputArgs::default();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("`{}` expects a value", name))
+            };
+            if output.arg(arg, &mut value)? {
+                continue;
+            }
+            match &arg[..] {
+                "-h" | "--help" => return Ok(Self::Help),
+                a if a.starts_with('-') => return Err(format!("unknown option `{}`", a)),
+                a => {
+                    if template.replace(a.to_string()).is_some() {
+                        return Err(format!("unexpected argument `{}`", a));
+                    }
+                }
+            }
+        }
+        Ok(Self::Wizard {
+            template: template.ok_or("no template given")?,
+            output: output.build()?,
+        })
+    }
+
+    fn parse_render(mut args: std::slice::Iter<String>) -> Result<Self, String> {
+        let mut template = None;
+        let mut ops = Vec::new();
+        let mut output = OutputArgs::default();
+        let mut check = false;
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("`{}` expects a value", name))
+            };
+            if output.arg(arg, &mut value)? {
+                continue;
+            }
+            match &arg[..] {
+                "--values" => ops.push(Op::Values(value(arg)?)),
+                "--set" => {
+                    let (k, v) = key_value(&value(arg)?)?;
+                    ops.push(Op::Set(k, v));
+                }
+                "--present" => ops.push(Op::Present(value(arg)?)),
+                "--list" => {
+                    // the entries before the last `/` lead to the list
+                    let key = value(arg)?;
+                    let (path, key) = match key.rsplit_once('/') {
+                        Some((path, key)) => (list_path(path)?, key.to_string()),
+                        None => (Vec::new(), key),
+                    };
+                    let vals = value(arg)?
+                        .split(',')
+                        .map(key_value)
+                        .collect::<Result<_, _>>()?;
+                    ops.push(Op::List(path, key, vals));
+                }
+                "--item" => {
+                    let path = list_path(&value(arg)?)?;
+                    let (k, v) = key_value(&value(arg)?)?;
+                    ops.push(Op::Item(path, k, v));
+                }
+                "-c" | "--check" => check = true,
+                "-h" | "--help" => return Ok(Self::Help),
+                a if a.starts_with('-') => return Err(format!("unknown option `{}`", a)),
+                a => {
+                    if template.replace(a.to_string()).is_some() {
+                        return Err(format!("unexpected argument `{}`", a));
+                    }
+                }
+            }
+        }
+        Ok(Self::Render {
+            template: template.ok_or("no template given")?,
+            ops,
+            output: output.build()?,
+            check,
+        })
+    }
+
+    pub fn run(self) -> Result<(), String> {
+        match self {
+            Self::Help => {
+                println!("{}", USAGE);
+                Ok(())
+            }
+            Self::Describe { template, json } => {
+                let params = Conf::parse(&template).map_err(|e| e.to_string())?.schema();
+                if json {
+                    let schema = schema::json_schema(&template, &params);
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&schema).map_err(|e| e.to_string())?
+                    );
+                } else {
+                    println!(
+                        "{:<24} {:<28} {:<9} {:<12} LINES",
+                        "NAME", "KIND", "OPTIONAL", "DEFAULT"
+                    );
+                    print_params(&params, 0);
+                }
+                Ok(())
+            }
+            Self::Lint { template } => {
+                let problems = lint::lint(&template);
+                if problems.is_empty() {
+                    println!("{}: ok", template);
+                    return Ok(());
+                }
+                let lines: Vec<_> = problems.iter().map(|e| format!("    {}", e)).collect();
+                Err(format!("{} has problems:\n{}", template, lines.join("\n")))
+            }
+            Self::Simulate { port, mut device } => {
+                let listener = std::net::TcpListener::bind(("127.0.0.1", port))
+                    .map_err(|e| format!("can't listen on port {}: {}", port, e))?;
+                eprintln!("listening on 127.0.0.1:{}", port);
+                // one connection at a time, printing what each one configured
+                for stream in listener.incoming() {
+                    let stream = stream.map_err(|e| e.to_string())?;
+                    let seen = device.running_config().len();
+                    if let Err(e) = device.serve(stream) {
+                        eprintln!("connection lost: {}", e);
+                    }
+                    for l in &device.running_config()[seen..] {
+                        println!("{}", l);
+                    }
+                }
+                Ok(())
+            }
+            Self::Plan {
+                block,
+                needs,
+                format,
+            } => {
+                let subnets = block.plan(&needs).map_err(|e| e.to_string())?;
+                match format {
+                    Some(f) => {
+                        let data = values::plan(&subnets);
+                        print!("{}", f.write(&data).map_err(|e| e.to_string())?);
+                    }
+                    None => print_plan(&subnets),
+                }
+                Ok(())
+            }
+            Self::Wizard { template, output } => {
+                let conf = Conf::parse(&template).map_err(|e| e.to_string())?;
+                // prompts go to stderr, so the config can still be piped from stdout
+                let stdin = std::io::stdin();
+                let conf = Wizard::new(stdin.lock(), std::io::stderr())
+                    .run(conf)
+                    .map_err(|e| e.to_string())?;
+                output
+                    .login_from(&conf)
+                    .write(conf.compile().map_err(|e| e.to_string())?)
+                    .map_err(|e| format!("failed to write output: {}", e))
+            }
+            Self::Render {
+                template,
+                ops,
+                output,
+                check,
+            } => {
+                let mut conf = Conf::parse(&template).map_err(|e| e.to_string())?;
+                let mut report = KeyReport::default();
+                for op in ops {
+                    // keep going after a bad value, so every problem is shown at once
+                    let prev = conf.clone();
+                    let res = match op {
+                        Op::Values(f) => {
+                            let data = values::read(&f).map_err(|e| format!("{}: {}", f, e))?;
+                            let (conf, r) = conf.apply_values(&data);
+                            report.unknown.extend(r.unknown);
+                            report.invalid.extend(r.invalid);
+                            Ok(conf)
+                        }
+                        Op::Set(k, v) => conf.set(k, v),
+                        Op::Present(k) => conf.set_present(k),
+                        Op::List(path, k, vals) => {
+                            let path: Vec<_> = path.iter().map(|(k, n)| (&k[..], *n)).collect();
+                            let vals = vals.iter().map(|(k, v)| (&k[..], &v[..])).collect();
+                            conf.add_list_item(&path, k, vals)
+                        }
+                        Op::Item(path, k, v) => {
+                            let path: Vec<_> = path.iter().map(|(k, n)| (&k[..], *n)).collect();
+                            conf.set_list_item(&path, k, v)
+                        }
+                    };
+                    conf = res.unwrap_or_else(|e| {
+                        report.add(e);
+                        prev
+                    });
+                }
+                conf.validate().into_iter().for_each(|e| report.add(e));
+                if !report.is_empty() {
+                    return Err(format!("can't render {}\n{}", template, report));
+                }
+                if check {
+                    println!("{}: ok", template);
+                    return Ok(());
+                }
+                output
+                    .login_from(&conf)
+                    .write(conf.compile().map_err(|e| e.to_string())?)
+                    .map_err(|e| format!("failed to write output: {}", e))
+            }
+        }
+    }
+}
+
+fn print_params(params: &[Param], depth: usize) {
+    for p in params {
+        println!(
+            "{:<24} {:<28} {:<9} {:<12} {}",
+            format!("{:indent$}{}", "", p.name, indent = depth * 2),
+            p.kind.to_string(),
+            if p.optional { "yes" } else { "no" },
+            p.default.as_deref().unwrap_or("-"),
+            p.line_names().join(",")
+        );
+        if let ParamKind::List(items) = &p.kind {
+            print_params(items, depth + 1);
+        }
+    }
+}
+
+fn print_plan(subnets: &[Subnet]) {
+    println!(
+        "{:<16} {:<7} {:<20} {:<17} {:<17} {:<17} {:<17} BROADCAST",
+        "NAME", "HOSTS", "NETWORK", "MASK", "GATEWAY", "FIRST", "LAST"
+    );
+    for s in subnets {
+        println!(
+            "{:<16} {:<7} {:<20} {:<17} {:<17} {:<17} {:<17} {:#}",
+            s.name,
+            s.hosts,
+            s.net.to_string(),
+            s.net.subnet_mask().to_string(),
+            format!("{:#}", s.gateway()),
+            format!("{:#}", s.first()),
+            format!("{:#}", s.last()),
+            s.broadcast()
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use crate::interface::InterfaceError;
+use crate::ip::IPError;
+use std::fmt;
+use std::ops::Range;
+
+/// What went wrong while parsing, filling or compiling a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The template couldn't be read
+    Io(String),
+    /// `{\type}` isn't one of the special types
+    UnknownType(String),
+    /// `{a..b}` doesn't contain two numbers
+    BadRange(String),
+    /// A `$` or `?` without a name, or a name with an unknown `-part`
+    BadName(String),
+    /// `|filter` isn't one of the known filters
+    UnknownFilter(String),
+    /// An address filter like `|network` on a parameter that isn't an `\ip`
+    NotAnIP(String),
+    /// A filter that only works on one type, e.g. `|link_local` on a `\mac` (filter, type)
+    FilterType(String, String),
+    /// A filter that takes a parameter without one, e.g. `|eui64` rather than `|eui64:mac`
+    FilterParam(String),
+    /// An address filter after a text filter, e.g. `$ip{\ip}|upper|network`
+    FilterAfterText(String, String),
+    /// An address filter other than `|mask` on a `dhcp` value
+    NoAddress(String),
+    /// `! for` isn't followed by a `$list`
+    BadFor,
+    /// `! if` isn't followed by `$param`, `$param == value` or `$param != value`
+    BadIf(String),
+    /// A block isn't closed, or is closed by the wrong thing, e.g. `! else` without `! if`
+    Unbalanced(String),
+    /// `! include` of a template that is already being included
+    IncludeCycle(String),
+    /// `! use` of a snippet that hasn't been defined yet
+    UnknownSnippet(String),
+    /// `! define` of a snippet that already exists
+    SnippetRedefined(String),
+    /// `! section` in a template that extends another, which doesn't have that section
+    UnknownSection(String),
+    /// A line in a template that extends another, but isn't in a `! section`
+    NotInSection,
+    /// A loop variable or `$^name` used outside of enough `! for`s
+    NotInLoop(String),
+    /// A parameter used with a different type or default (here, elsewhere, where elsewhere is)
+    ConflictingType(String, String, String),
+    /// A `?\` line without any parameters, which is always kept
+    NoParams,
+    /// The key isn't a parameter of the template
+    UnknownParam,
+    /// The key is a `! for` list, but was used as a value
+    IsList,
+    /// The key was used as a `! for` list, but isn't one
+    NotAList,
+    /// The list doesn't have that many entries
+    NoEntry(usize),
+    /// `set_present` was used on a key with more (or less) than one option
+    NotPresent,
+    /// The value isn't one of the options listed in `{}`
+    InvalidChoice(String, Vec<String>),
+    /// The value isn't a number
+    NotANumber(String),
+    /// The value is outside of the range listed in `{}`
+    OutOfRange(isize, Range<isize>),
+    BadIP(IPError),
+    BadInterface(InterfaceError),
+    /// A required parameter wasn't given a value
+    Missing,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::UnknownType(t) => write!(f, "`{}` isn't a valid type", t),
+            Self::BadRange(r) => write!(f, "`{}` isn't a valid range", r),
+            Self::BadName(n) => write!(f, "`{}` isn't a valid parameter name", n),
+            Self::UnknownFilter(n) => write!(f, "`{}` isn't a valid filter", n),
+            Self::NotAnIP(n) => write!(f, "`|{}` only works on address parameters, e.g. `\\ip`", n),
+            Self::FilterType(n, t) => write!(f, "`|{}` only works on `{}` parameters", n, t),
+            Self::FilterParam(n) => write!(f, "`|{}` needs a parameter, e.g. `|{}:mac`", n, n),
+            Self::FilterAfterText(ip, text) => {
+                write!(
+                    f,
+                    "`|{}` can't come after `|{}`, which made it text",
+                    ip, text
+                )
+            }
+            Self::NoAddress(n) => write!(f, "`|{}` needs an address, not dhcp", n),
+            Self::BadFor => write!(f, "`for` must be followed by a `$list`"),
+            Self::BadIf(c) => write!(f, "`{}` isn't a valid condition", c),
+            Self::Unbalanced(m) => write!(f, "{}", m),
+            Self::IncludeCycle(t) => write!(f, "including `{}` would loop forever", t),
+            Self::UnknownSnippet(n) => write!(f, "no snippet `{}` has been defined", n),
+            Self::SnippetRedefined(n) => write!(f, "snippet `{}` is already defined", n),
+            Self::UnknownSection(n) => write!(f, "the base template has no section `{}`", n),
+            Self::NotInSection => write!(
+                f,
+                "a template that extends another can only change `! section`s"
+            ),
+            Self::NotInLoop(n) => write!(f, "`{}` can only be used inside a `! for`", n),
+            Self::ConflictingType(here, there, at) => {
+                write!(f, "used as {} here, but as {} at {}", here, there, at)
+            }
+            Self::NoParams => write!(f, "`?\\` line has no parameters, so it's always kept"),
+            Self::UnknownParam => write!(f, "not a parameter of the template"),
+            Self::IsList => write!(f, "is a list, not a value"),
+            Self::NotAList => write!(f, "isn't a list"),
+            Self::NoEntry(n) => write!(f, "list doesn't have an entry {}", n),
+            Self::NotPresent => write!(f, "doesn't have exactly one option"),
+            Self::InvalidChoice(v, l) => write!(f, "`{}` isn't one of {}", v, l.join(", ")),
+            Self::NotANumber(v) => write!(f, "`{}` isn't a number", v),
+            Self::OutOfRange(v, r) => write!(f, "{} isn't in {}..{}", v, r.start, r.end),
+            Self::BadIP(e) => write!(f, "{}", e),
+            Self::BadInterface(e) => write!(f, "{}", e),
+            Self::Missing => write!(f, "required value not supplied"),
+        }
+    }
+}
+
+/**
+ * Error with as much location info as is known
+ *
+ * Lines and columns count from 1, and point into the template
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfError {
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub param: Option<String>,
+    pub kind: ErrorKind,
+}
+
+impl ConfError {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            file: None,
+            line: None,
+            column: None,
+            param: None,
+            kind,
+        }
+    }
+    pub fn file(mut self, file: impl Into<String>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+    /// Sets the file of a node from an included template, `None` being the main one
+    pub fn in_file(self, file: Option<&str>) -> Self {
+        match file {
+            Some(f) => self.file(f),
+            None => self,
+        }
+    }
+    /// Sets the file, unless the error already came from another one (e.g. an include)
+    pub fn or_file(self, file: impl Into<String>) -> Self {
+        match self.file {
+            Some(_) => self,
+            None => self.file(file),
+        }
+    }
+    pub fn at(mut self, line: usize, column: usize) -> Self {
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+    pub fn param(mut self, param: impl Into<String>) -> Self {
+        self.param = Some(param.into());
+        self
+    }
+}
+
+impl From<ErrorKind> for ConfError {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl fmt::Display for ConfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, "{}:", line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, "{}:", column)?;
+        }
+        if self.file.is_some() || self.line.is_some() {
+            write!(f, " ")?;
+        }
+        if let Some(param) = &self.param {
+            write!(f, "{}: ", param)?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl std::error::Error for ConfError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_what_is_known() {
+        let e = ConfError::new(ErrorKind::Missing);
+        assert_eq!(e.to_string(), "required value not supplied");
+        let e = e.param("hostname");
+        assert_eq!(e.to_string(), "hostname: required value not supplied");
+        let e = e.at(3, 10);
+        assert_eq!(e.to_string(), "3:10: hostname: required value not supplied");
+        let e = e.file("router.conf");
+        assert_eq!(
+            e.to_string(),
+            "router.conf:3:10: hostname: required value not supplied"
+        );
+        let e = ConfError::new(ErrorKind::OutOfRange(5, 0..5)).file("a.conf");
+        assert_eq!(e.to_string(), "a.conf: 5 isn't in 0..5");
+    }
+}
@@ -1,52 +1,117 @@
 use std::fs;
-use std::io::{ErrorKind, Read};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
 /**
  * Struct for generic file reading
  *
- * Can be read using a variety of iterators
+ * Reads a line at a time through a buffer, dropping the `\n` or `\r\n`
+ * at the end of each line, and counts lines as it goes so errors can say
+ * where they came from.
  */
-pub struct File {
+pub struct File<R = fs::File> {
     name: String,
-    file: fs::File,
+    reader: BufReader<R>,
+    line: usize,
 }
 
 impl File {
-    pub fn read(name: &str) -> std::io::Result<File> {
-        Ok(File {
+    pub fn read(name: &str) -> io::Result<File> {
+        Ok(File::new(name, fs::File::open(name)?))
+    }
+}
+
+impl<R: Read> File<R> {
+    /// Reads lines from anything, `name` is only used to describe it
+    pub fn new(name: &str, reader: R) -> Self {
+        File {
             name: name.to_owned(),
-            file: fs::File::open(name)?,
-        })
+            reader: BufReader::new(reader),
+            line: 0,
+        }
+    }
+
+    /// The number of the last line read (or that failed to read), counting from 1
+    pub fn line(&self) -> usize {
+        self.line
     }
 }
 
-impl std::iter::Iterator for File {
-    type Item = String;
-    fn next(&mut self) -> Option<String> {
-        let mut ret = String::default();
-        loop {
-            let mut buf = [0u8];
-            match self.file.read(&mut buf) {
-                Ok(n) => {
-                    if n <= 0 {
-                        return None;
-                    }
-                }
-                Err(e) => match e.kind() {
-                    ErrorKind::UnexpectedEof => return None,
-                    _ => panic!("Something happened: {:?}", e),
-                },
-            }
-            if buf[0] as char == '\n' {
-                return Some(ret);
-            } else {
-                ret.push(buf[0] as char);
+impl<R: Read> std::iter::Iterator for File<R> {
+    type Item = io::Result<String>;
+    fn next(&mut self) -> Option<io::Result<String>> {
+        let mut buf = Vec::new();
+        let read = self.reader.read_until(b'\n', &mut buf);
+        if let Ok(0) = read {
+            return None;
+        }
+        self.line += 1;
+        if let Err(e) = read {
+            return Some(Err(e));
+        }
+        if buf.ends_with(b"\n") {
+            buf.pop();
+            if buf.ends_with(b"\r") {
+                buf.pop();
             }
         }
+        Some(String::from_utf8(buf).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("isn't valid UTF-8: {}", e.utf8_error()),
+            )
+        }))
     }
 }
 
-impl std::fmt::Display for File {
+impl<R> std::fmt::Display for File<R> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "File: {}", self.name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(bytes: &[u8]) -> Vec<io::Result<String>> {
+        File::new("test", bytes).collect()
+    }
+
+    fn ok(bytes: &[u8]) -> Vec<String> {
+        lines(bytes).into_iter().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(ok(b"a\nb\n"), ["a", "b"]);
+        assert_eq!(ok(b"a\r\nb\r\n"), ["a", "b"]);
+        // a lone `\r` is kept, only `\r\n` is a line ending
+        assert_eq!(ok(b"a\rb\n"), ["a\rb"]);
+        assert_eq!(ok(b"a\n\nb"), ["a", "", "b"]);
+        assert!(ok(b"").is_empty());
+    }
+
+    #[test]
+    fn no_final_newline() {
+        assert_eq!(ok(b"a\r\nlast"), ["a", "last"]);
+        assert_eq!(ok(b"only"), ["only"]);
+    }
+
+    #[test]
+    fn bad_utf8() {
+        let mut file = File::new("test", &b"fine\nbad \xff here\nafter\n"[..]);
+        assert_eq!(file.next().unwrap().unwrap(), "fine");
+        let e = file.next().unwrap().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert!(e.to_string().starts_with("isn't valid UTF-8"), "{}", e);
+        assert_eq!(file.line(), 2);
+        // the rest of the file can still be read
+        assert_eq!(file.next().unwrap().unwrap(), "after");
+        assert!(file.next().is_none());
+    }
+
+    #[test]
+    fn missing_file() {
+        let e = File::read("tests/data/none.conf").err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+    }
+}
@@ -16,15 +16,39 @@ pub struct Interface {
     num_s: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum Iface {
     Range(Range<Interface>),
     Single(Interface),
+    #[default]
     None,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceError {
+    /// The name doesn't start with a known interface type
+    UnknownType(String),
+    /// Part of the interface number isn't a number
+    BadNumber(String),
+}
+
+impl std::fmt::Display for InterfaceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownType(s) => write!(f, "`{}` isn't a known interface type", s),
+            Self::BadNumber(s) => write!(f, "`{}` isn't a valid interface number", s),
+        }
+    }
+}
+
+fn iface_num(s: &str) -> Result<i8, InterfaceError> {
+    s.trim()
+        .parse()
+        .map_err(|_| InterfaceError::BadNumber(s.to_string()))
+}
+
 impl Iface {
-    pub fn parse(s: &str) -> Self {
+    pub fn parse(s: &str) -> Result<Self, InterfaceError> {
         let s = s.trim().to_ascii_lowercase();
         let itype = if s.starts_with("g") {
             InterfaceType::GigabitEthernet
@@ -37,7 +61,7 @@ impl Iface {
         } else if s.starts_with("l") {
             InterfaceType::Loopback
         } else {
-            panic!("Malformed Interface type")
+            return Err(InterfaceError::UnknownType(s));
         };
         let mut num = ((-1i8, -1i8), (-1i8, -1i8), (-1i8, -1i8));
         let mut nums = s
@@ -46,51 +70,47 @@ impl Iface {
             .skip_while(|c| c.is_alphabetic());
         let a = nums.by_ref().take_while(|&c| c != '/').collect::<String>();
         let mut a = a.split("-");
-        num.0 .0 = a
-            .next()
-            .expect("Doesn't have a number")
-            .parse()
-            .expect("Wasn't a number");
+        num.0 .0 = iface_num(a.next().unwrap_or_default())?;
         if let Some(a) = a.next() {
-            num.0 .1 = a.parse().expect("Wasn't a number");
+            num.0 .1 = iface_num(a)?;
         } else {
             num.0 .1 = num.0 .0;
         }
         let a = nums.by_ref().take_while(|&c| c != '/').collect::<String>();
-        let mut a = a.split("-").filter(|&s| s != "");
+        let mut a = a.split("-").filter(|&s| !s.is_empty());
         if let Some(n) = a.next() {
-            num.1 .0 = n.parse().expect("Wasn't a number");
+            num.1 .0 = iface_num(n)?;
             if let Some(a) = a.next() {
-                num.1 .1 = a.parse().expect("Wasn't a number");
+                num.1 .1 = iface_num(a)?;
             } else {
                 num.1 .1 = num.1 .0;
             }
             let a = nums.by_ref().take_while(|&c| c != '/').collect::<String>();
-            let mut a = a.split("-").filter(|&s| s != "");
+            let mut a = a.split("-").filter(|&s| !s.is_empty());
             if let Some(n) = a.next() {
-                num.1 .0 = n.parse().expect("Wasn't a number");
+                num.2 .0 = iface_num(n)?;
                 if let Some(a) = a.next() {
-                    num.1 .1 = a.parse().expect("Wasn't a number");
+                    num.2 .1 = iface_num(a)?;
                 } else {
                     num.2 .1 = num.2 .0;
                 }
             }
         }
-        if num.0 .1 == -1 && num.1 .1 == -1 && num.2 .1 == -1 {
-            Iface::Single(Interface::new(
+        if num.0 .0 == num.0 .1 && num.1 .0 == num.1 .1 && num.2 .0 == num.2 .1 {
+            Ok(Iface::Single(Interface::new(
                 itype,
                 (num.0 .0 as u8, num.1 .0 as u8, num.2 .0 as u8),
-            ))
+            )))
         } else {
-            Iface::Range(
+            Ok(Iface::Range(
                 Interface::new(itype, (num.0 .0 as u8, num.1 .0 as u8, num.2 .0 as u8))
                     ..Interface::new(itype, (num.0 .1 as u8, num.1 .1 as u8, num.2 .1 as u8)),
-            )
+            ))
         }
     }
     pub fn fmt(&self) -> String {
         match self {
-            Self::None => format!(""),
+            Self::None => String::new(),
             Self::Single(i) => format!("{} {}", i.fmt_name(), i.fmt_num()),
             Self::Range(r) => format!(
                 "range {} {}",
@@ -101,16 +121,7 @@ impl Iface {
     }
 
     pub fn is_none(&self) -> bool {
-        match self {
-            Self::None => true,
-            _ => false,
-        }
-    }
-}
-
-impl Default for Iface {
-    fn default() -> Self {
-        Self::None
+        matches!(self, Self::None)
     }
 }
 
@@ -165,7 +176,7 @@ impl Interface {
                 Self::fmt_range(self.num.1, other.num.1),
                 Self::fmt_range(self.num.2, other.num.2)
             ),
-            InterfaceType::Loopback => format!("{}", Self::fmt_range(self.num.0, other.num.0)),
+            InterfaceType::Loopback => Self::fmt_range(self.num.0, other.num.0).to_string(),
             InterfaceType::Ethernet
             | InterfaceType::FastEthernet
             | InterfaceType::GigabitEthernet => format!(
@@ -184,42 +195,39 @@ impl Interface {
     }
 }
 
+#[allow(unused)]
 fn split(s: &str) -> Vec<String> {
     let mut ret = Vec::new();
     let mut cur = String::default();
     let mut t = 0;
     for c in s.chars() {
         match t {
-            1 => {
-                if c.is_alphabetic() {
-                    cur.push(c);
-                    break;
-                }
+            1 if c.is_alphabetic() => {
+                cur.push(c);
+                break;
             }
-            2 => {
-                if c.is_numeric() {
-                    cur.push(c);
-                    break;
-                }
+            2 if c.is_numeric() => {
+                cur.push(c);
+                break;
             }
             _ => (),
         }
         if c.is_alphabetic() {
-            if cur != "" {
+            if !cur.is_empty() {
                 ret.push(cur);
             }
             cur = String::default();
             cur.push(c);
             t = 1;
         } else if c.is_numeric() {
-            if cur != "" {
+            if !cur.is_empty() {
                 ret.push(cur);
             }
             cur = String::default();
             cur.push(c);
             t = 2;
         } else if c.is_numeric() {
-            if cur != "" {
+            if !cur.is_empty() {
                 ret.push(cur);
             }
             cur = String::default();
@@ -229,3 +237,54 @@ fn split(s: &str) -> Vec<String> {
     }
     ret
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(
+            Iface::parse("f0/1").unwrap().fmt(),
+            Iface::parse("FastEthernet 0/1").unwrap().fmt()
+        );
+        assert!(Iface::parse("g0/0")
+            .unwrap()
+            .fmt()
+            .contains("GigabitEthernet 0/0"));
+    }
+
+    #[test]
+    fn single_or_range() {
+        assert_eq!(Iface::parse("g0/0").unwrap().fmt(), "GigabitEthernet 0/0");
+        assert_eq!(Iface::parse("s0/0/1").unwrap().fmt(), "Serial 0/0/1");
+        assert_eq!(
+            Iface::parse("f0/1-4").unwrap().fmt(),
+            "range FastEthernet 0/1 - 4"
+        );
+        assert_eq!(
+            Iface::parse("s0/0/0-1").unwrap().fmt(),
+            "range Serial 0/0/0 - 1"
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            Iface::parse("x0/1").unwrap_err(),
+            InterfaceError::UnknownType("x0/1".to_string())
+        );
+        assert_eq!(
+            Iface::parse("g0/a").unwrap_err(),
+            InterfaceError::BadNumber("a".to_string())
+        );
+        assert_eq!(
+            Iface::parse("g").unwrap_err(),
+            InterfaceError::BadNumber("".to_string())
+        );
+        assert_eq!(
+            Iface::parse("nope").unwrap_err().to_string(),
+            "`nope` isn't a known interface type"
+        );
+    }
+}
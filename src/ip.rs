@@ -1,5 +1,5 @@
-use std::fmt;
 use std::default::Default;
+use std::fmt;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum IPType {
@@ -21,8 +21,58 @@ pub enum IPClass {
     Unknown,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum IPError {
-    BadMask(u32),
+    /// A mask whose ones aren't all at the start, wide enough for IPv6
+    BadMask(u128),
+    /// Part of the address isn't a number from 0 to 255
+    BadPart(String),
+    /// Fewer than 4 parts in the address
+    MissingPart,
+    /// More than 4 parts, or more than one `/`
+    TooManyParts,
+    /// No `/` with a mask length
+    MissingMask,
+    /// The mask length isn't a number from 0 to 32 (128 for IPv6)
+    BadMaskLen(String),
+    /// Part of an IPv6 address isn't 1 to 4 hex digits
+    BadGroup(String),
+    /// An IPv6 address without `::` that doesn't have 8 groups, or one with `::` and more than 7
+    GroupCount,
+    /// More than one `::` in an IPv6 address
+    DoubleGap,
+    /// Not a mac address, for EUI-64
+    BadMac(String),
+    /// EUI-64 fills the last 64 bits, so the prefix can't be longer than that
+    PrefixTooLong(u32),
+    /// A planned subnet (name, hosts) that doesn't fit, with the addresses the plan needs
+    /// and the size of the block
+    BlockFull(String, u32, u64, u64),
+}
+
+impl fmt::Display for IPError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMask(m) => write!(f, "{:#x} isn't a valid subnet mask", m),
+            Self::BadPart(p) => write!(f, "`{}` is not a valid ip part", p),
+            Self::MissingPart => write!(f, "expected 4 numbers in the address"),
+            Self::TooManyParts => write!(f, "too many `.` or `/` in the address"),
+            Self::MissingMask => write!(f, "mask not provided, expected `/<bits>`"),
+            Self::BadMaskLen(m) => write!(f, "`{}` is not a valid mask length", m),
+            Self::BadGroup(g) => write!(f, "`{}` is not a valid ipv6 group", g),
+            Self::GroupCount => write!(f, "expected 8 groups in the address, or fewer with `::`"),
+            Self::DoubleGap => write!(f, "only one `::` is allowed in the address"),
+            Self::BadMac(m) => write!(f, "`{}` is not a valid mac address", m),
+            Self::PrefixTooLong(b) => {
+                write!(f, "EUI-64 needs a /64 or shorter prefix, not /{}", b)
+            }
+            Self::BlockFull(name, hosts, need, have) => write!(
+                f,
+                "no room for `{}` ({} hosts): the plan needs {} addresses, but the block has {}",
+                name, hosts, need, have
+            ),
+        }
+    }
 }
 
 pub trait IP: fmt::Display + Copy + Eq {
@@ -32,6 +82,8 @@ pub trait IP: fmt::Display + Copy + Eq {
     fn ip_type(&self) -> IPType;
     fn network(&self) -> Self;
     fn subnet_mask(&self) -> Self;
+    /// The inverse of the subnet mask, as used by ACLs and OSPF
+    fn wildcard(&self) -> Self;
     fn subnet_addr(&self) -> Self;
     fn subnet_net(&self) -> Self;
     fn subnet_net_num(&self) -> Self;
@@ -51,61 +103,60 @@ pub struct IPv4 {
 }
 
 impl IPv4 {
-    fn ip_part(s: Option<&str>, part: u32) -> u32 {
+    fn ip_part(s: Option<&str>, part: u32) -> Result<u32, IPError> {
+        let s = s.ok_or(IPError::MissingPart)?;
         let ret = s
-            .expect("Expected at least 4 nums")
             .parse::<u32>()
-            .expect("IP parts are ints");
+            .map_err(|_| IPError::BadPart(s.to_string()))?;
         if ret > 0xFF {
-            panic!("{} is not a valid ip part", ret);
+            return Err(IPError::BadPart(s.to_string()));
         }
-        ret << part
+        Ok(ret << part)
     }
-    pub fn parse(ip: &str) -> Self {
+    pub fn parse(ip: &str) -> Result<Self, IPError> {
         if ip == "dhcp" {
-            Self {
+            Ok(Self {
                 ip: 0,
                 mask: 0,
                 super_mask: 0,
                 ip_type: IPType::DHCP,
-            }
+            })
         } else {
             let mut tmp = ip.split('/');
             // ip addr
-            let mut ip_split = tmp.next().expect("Address expected").split('.');
-            let ip = Self::ip_part(ip_split.next(), 24)
-                | Self::ip_part(ip_split.next(), 16)
-                | Self::ip_part(ip_split.next(), 8)
-                | Self::ip_part(ip_split.next(), 0);
-            if !ip_split.next().is_none() {
-                panic!("Too many `.` in ip: {}", ip);
+            let mut ip_split = tmp.next().ok_or(IPError::MissingPart)?.split('.');
+            let ip = Self::ip_part(ip_split.next(), 24)?
+                | Self::ip_part(ip_split.next(), 16)?
+                | Self::ip_part(ip_split.next(), 8)?
+                | Self::ip_part(ip_split.next(), 0)?;
+            if ip_split.next().is_some() {
+                return Err(IPError::TooManyParts);
             }
             // mask
-            let mask = u32::max_value()
-                << (32
-                    - tmp
-                        .next()
-                        .expect("Mask not provided")
-                        .parse::<u32>()
-                        .expect("Mask was not int"));
+            let len = tmp.next().ok_or(IPError::MissingMask)?;
+            let bits = match len.parse::<u32>() {
+                Ok(b) if b <= 32 => b,
+                _ => return Err(IPError::BadMaskLen(len.to_string())),
+            };
+            let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
             // check format
-            if !tmp.next().is_none() {
-                panic!("Too many `/` in ip: {}", ip);
+            if tmp.next().is_some() {
+                return Err(IPError::TooManyParts);
             }
             if ip & mask == 0 {
-                Self {
+                Ok(Self {
                     ip,
                     mask,
                     super_mask: 0,
                     ip_type: IPType::Network,
-                }
+                })
             } else {
-                Self {
+                Ok(Self {
                     ip,
                     mask,
                     super_mask: 0,
                     ip_type: IPType::Public,
-                }
+                })
             }
         }
     }
@@ -128,7 +179,7 @@ impl IPv4 {
             }
         }
         // sub sub net bits = bits
-        let mask = u32::max_value() << (32 - (network.mask_num() + bits));
+        let mask = u32::MAX << (32 - (network.mask_num() + bits));
         let mut ret = Vec::new();
         for i in 0..1 << bits {
             ret.push(Self {
@@ -140,6 +191,71 @@ impl IPv4 {
         }
         ret
     }
+    /**
+     * Splits the block into subnets of different sizes (VLSM), unlike `build_net`
+     *
+     * Each subnet is the smallest with room for its hosts, given out largest
+     * first (in the order asked for on a tie) so every one lines up on its
+     * size. Fails if the block runs out.
+     */
+    pub fn plan(self, needs: &[(String, u32)]) -> Result<Vec<Subnet>, IPError> {
+        let mut needs: Vec<_> = needs.iter().collect();
+        needs.sort_by_key(|(_, hosts)| std::cmp::Reverse(*hosts));
+        // room for the hosts as well as the network and broadcast addresses
+        let size = |hosts: u32| (hosts as u64 + 2).next_power_of_two().max(4);
+        let block = self.network();
+        let have = !block.mask as u64 + 1;
+        let mut used = 0;
+        let mut ret = Vec::new();
+        for (name, hosts) in needs.iter().copied() {
+            let len = size(*hosts);
+            if used + len > have {
+                let need = needs.iter().map(|(_, h)| size(*h)).sum();
+                return Err(IPError::BlockFull(name.clone(), *hosts, need, have));
+            }
+            ret.push(Subnet {
+                name: name.clone(),
+                hosts: *hosts,
+                net: Self {
+                    ip: block.ip + used as u32,
+                    mask: !(len - 1) as u32,
+                    super_mask: block.mask,
+                    ip_type: IPType::Network,
+                },
+            });
+            used += len;
+        }
+        Ok(ret)
+    }
+}
+
+/** A named subnet handed out by `IPv4::plan`
+
+The gateway is the first usable address, and the hosts get the rest up to
+the broadcast.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subnet {
+    pub name: String,
+    /// Usable addresses asked for, counting the gateway
+    pub hosts: u32,
+    pub net: IPv4,
+}
+
+impl Subnet {
+    pub fn gateway(&self) -> IPv4 {
+        self.net.first()
+    }
+    /// The first address after the gateway
+    pub fn first(&self) -> IPv4 {
+        self.gateway().next().unwrap_or_else(|| self.gateway())
+    }
+    pub fn last(&self) -> IPv4 {
+        self.net.last()
+    }
+    pub fn broadcast(&self) -> IPv4 {
+        self.net.broadcast()
+    }
 }
 
 impl Default for IPv4 {
@@ -155,28 +271,33 @@ impl Default for IPv4 {
 
 impl IP for IPv4 {
     fn next(&self) -> Option<Self> {
-        if self.ip & self.mask != (self.ip + 1) & self.mask {
+        let ip = self.ip.checked_add(1)?;
+        if self.ip & self.mask != ip & self.mask {
             None
         } else {
             Some(Self {
-                ip: self.ip + 1,
+                ip,
                 mask: self.mask,
                 super_mask: self.super_mask,
                 ip_type: self.ip_type,
             })
         }
     }
+    /// The first host address, the network itself for a /31 or /32 (RFC 3021)
     fn first(&self) -> Self {
+        let (net, bcast) = (self.ip & self.mask, self.ip | !self.mask);
         Self {
-            ip: (self.ip & self.mask) + 1,
+            ip: if bcast - net < 2 { net } else { net + 1 },
             mask: self.mask,
             super_mask: self.super_mask,
             ip_type: self.ip_type,
         }
     }
+    /// The last host address, the broadcast itself for a /31 or /32 (RFC 3021)
     fn last(&self) -> Self {
+        let (net, bcast) = (self.ip & self.mask, self.ip | !self.mask);
         Self {
-            ip: (self.ip & self.mask) + (u32::max_value() & !self.mask) - 1,
+            ip: if bcast - net < 2 { bcast } else { bcast - 1 },
             mask: self.mask,
             super_mask: self.super_mask,
             ip_type: self.ip_type,
@@ -201,7 +322,7 @@ impl IP for IPv4 {
                 super_mask: 0,
                 ip_type: IPType::Empty,
             }
-        }else {
+        } else {
             Self {
                 ip: self.mask,
                 mask: 0,
@@ -210,6 +331,17 @@ impl IP for IPv4 {
             }
         }
     }
+    fn wildcard(&self) -> Self {
+        let mask = self.subnet_mask();
+        Self {
+            ip: if mask.ip_type == IPType::Empty {
+                0
+            } else {
+                !mask.ip
+            },
+            ..mask
+        }
+    }
     fn subnet_addr(&self) -> Self {
         Self {
             ip: self.ip & (!self.mask),
@@ -236,7 +368,7 @@ impl IP for IPv4 {
     }
     fn broadcast(&self) -> Self {
         Self {
-            ip: (self.ip & self.mask) + (u32::max_value() & !self.mask),
+            ip: self.ip | !self.mask,
             mask: self.mask,
             super_mask: self.super_mask,
             ip_type: IPType::Broadcast,
@@ -256,11 +388,11 @@ impl IP for IPv4 {
             if self.mask & (1 << i) == 0 {
                 end = true;
             } else if end {
-                return Err(IPError::BadMask(self.mask));
+                return Err(IPError::BadMask(self.mask.into()));
             }
         }
         if self.mask & 1 == 1 {
-            return Err(IPError::BadMask(self.mask));
+            return Err(IPError::BadMask(self.mask.into()));
         }
         Ok(())
     }
@@ -324,3 +456,764 @@ impl fmt::Binary for IPv4 {
         }
     }
 }
+
+const IP_6_GROUP: u128 = 0xFFFF; // 16 bits
+/** An IPv6 address with its prefix length.
+
+Parsing and printing follow RFC 4291 and RFC 5952, so `2001:DB8:0:0::1/64`
+prints as `2001:db8::1/64`. IPv6 has no broadcast address; `broadcast` and
+`last` are both the all-ones address of the prefix.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IPv6 {
+    ip: u128,
+    mask: u128,
+    super_mask: u128,
+    ip_type: IPType,
+}
+
+impl IPv6 {
+    /// Reads groups separated by `:`, an empty string being no groups
+    fn groups(s: &str) -> Result<Vec<u128>, IPError> {
+        if s.is_empty() {
+            return Ok(Vec::new());
+        }
+        s.split(':')
+            .map(|g| {
+                if g.is_empty() || g.len() > 4 {
+                    return Err(IPError::BadGroup(g.to_string()));
+                }
+                u128::from_str_radix(g, 16).map_err(|_| IPError::BadGroup(g.to_string()))
+            })
+            .collect()
+    }
+    fn addr(s: &str) -> Result<u128, IPError> {
+        // an IPv4 address can fill the last two groups, e.g. `::ffff:192.0.2.1`
+        let s = match s.rfind(':') {
+            Some(i) if s[i + 1..].contains('.') => {
+                let mut parts = s[i + 1..].split('.');
+                let mut v4 = 0;
+                for shift in [24, 16, 8, 0] {
+                    v4 |= IPv4::ip_part(parts.next(), shift)?;
+                }
+                if parts.next().is_some() {
+                    return Err(IPError::TooManyParts);
+                }
+                format!("{}{:x}:{:x}", &s[..=i], v4 >> 16, v4 & 0xFFFF)
+            }
+            _ => s.to_string(),
+        };
+        let (head, tail) = match s.find("::") {
+            Some(i) => (&s[..i], Some(&s[i + 2..])),
+            None => (&s[..], None),
+        };
+        let head = Self::groups(head)?;
+        let tail = match tail {
+            Some(t) if t.contains("::") => return Err(IPError::DoubleGap),
+            Some(t) => Self::groups(t)?,
+            None if head.len() != 8 => return Err(IPError::GroupCount),
+            None => Vec::new(),
+        };
+        if tail.len() + head.len() > 7 && s.contains("::") {
+            return Err(IPError::GroupCount);
+        }
+        let ret = head.iter().fold(0u128, |ret, g| ret << 16 | g);
+        let ret = ret
+            .checked_shl(16 * (8 - head.len() - tail.len()) as u32)
+            .unwrap_or(0);
+        Ok(tail.iter().fold(ret, |ret, g| ret << 16 | g))
+    }
+    pub fn parse(ip: &str) -> Result<Self, IPError> {
+        if ip == "dhcp" {
+            return Ok(Self {
+                ip_type: IPType::DHCP,
+                ..Self::default()
+            });
+        }
+        let mut tmp = ip.split('/');
+        let ip = Self::addr(tmp.next().ok_or(IPError::MissingPart)?)?;
+        // prefix
+        let len = tmp.next().ok_or(IPError::MissingMask)?;
+        let bits = match len.parse::<u32>() {
+            Ok(b) if b <= 128 => b,
+            _ => return Err(IPError::BadMaskLen(len.to_string())),
+        };
+        if tmp.next().is_some() {
+            return Err(IPError::TooManyParts);
+        }
+        let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+        Ok(Self {
+            ip,
+            mask,
+            super_mask: 0,
+            ip_type: if ip & !mask == 0 {
+                IPType::Network
+            } else {
+                IPType::Public
+            },
+        })
+    }
+    fn mask_num(&self) -> u32 {
+        self.mask.count_ones()
+    }
+    /// Reads `aa:bb:cc:dd:ee:ff`, `aa-bb-cc-dd-ee-ff` or `aabb.ccdd.eeff`
+    fn mac(mac: &str) -> Result<u128, IPError> {
+        let hex: String = mac.chars().filter(|c| !":-.".contains(*c)).collect();
+        if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(IPError::BadMac(mac.to_string()));
+        }
+        u128::from_str_radix(&hex, 16).map_err(|_| IPError::BadMac(mac.to_string()))
+    }
+    /** The address in this /64 (or shorter) prefix whose interface id is the
+    modified EUI-64 of `mac`: `fffe` goes in the middle of the mac and the
+    universal/local bit is flipped.
+    */
+    pub fn eui64(&self, mac: &str) -> Result<Self, IPError> {
+        if self.mask_num() > 64 {
+            return Err(IPError::PrefixTooLong(self.mask_num()));
+        }
+        let mac = Self::mac(mac)?;
+        let id = (mac >> 24) << 40 | 0xFFFE << 24 | (mac & 0xFF_FFFF);
+        Ok(Self {
+            ip: (self.ip & !0 << 64) | id ^ 0x02 << 56,
+            ip_type: IPType::Public,
+            ..*self
+        })
+    }
+    /// The `fe80::/64` link-local address for `mac`
+    pub fn link_local(mac: &str) -> Result<Self, IPError> {
+        Self {
+            ip: 0xFE80 << 112,
+            mask: u128::MAX << 64,
+            super_mask: 0,
+            ip_type: IPType::Network,
+        }
+        .eui64(mac)
+    }
+}
+
+impl Default for IPv6 {
+    fn default() -> Self {
+        Self {
+            ip: 0,
+            mask: 0,
+            super_mask: 0,
+            ip_type: IPType::None,
+        }
+    }
+}
+
+impl IP for IPv6 {
+    fn next(&self) -> Option<Self> {
+        let ip = self.ip.checked_add(1)?;
+        if self.ip & self.mask != ip & self.mask {
+            None
+        } else {
+            Some(Self { ip, ..*self })
+        }
+    }
+    /// The address after the network, or the network itself for a /127 or /128 (RFC 6164)
+    fn first(&self) -> Self {
+        let (net, top) = (self.ip & self.mask, self.ip | !self.mask);
+        Self {
+            ip: if top - net < 2 { net } else { net + 1 },
+            ..*self
+        }
+    }
+    /// The all-ones address of the prefix, which IPv6 can give a host as it has no broadcast
+    fn last(&self) -> Self {
+        Self {
+            ip: self.ip | !self.mask,
+            ..*self
+        }
+    }
+    fn ip_type(&self) -> IPType {
+        self.ip_type
+    }
+    fn network(&self) -> Self {
+        Self {
+            ip: self.ip & self.mask,
+            ip_type: IPType::Network,
+            ..*self
+        }
+    }
+    fn subnet_mask(&self) -> Self {
+        if self.ip_type == IPType::DHCP {
+            Self {
+                ip_type: IPType::Empty,
+                ..Self::default()
+            }
+        } else {
+            Self {
+                ip: self.mask,
+                mask: 0,
+                super_mask: 0,
+                ip_type: IPType::SubnetMask,
+            }
+        }
+    }
+    fn wildcard(&self) -> Self {
+        let mask = self.subnet_mask();
+        Self {
+            ip: if mask.ip_type == IPType::Empty {
+                0
+            } else {
+                !mask.ip
+            },
+            ..mask
+        }
+    }
+    fn subnet_addr(&self) -> Self {
+        Self {
+            ip: self.ip & !self.mask,
+            ip_type: IPType::SubnetAddr,
+            ..*self
+        }
+    }
+    fn subnet_net(&self) -> Self {
+        Self {
+            ip: self.ip & (self.mask ^ self.super_mask),
+            ip_type: IPType::SubnetAddr,
+            ..*self
+        }
+    }
+    fn subnet_net_num(&self) -> Self {
+        Self {
+            ip: (self.ip & (self.mask ^ self.super_mask))
+                .checked_shr(128 - self.mask_num())
+                .unwrap_or(0),
+            ip_type: IPType::SubnetAddr,
+            ..*self
+        }
+    }
+    fn broadcast(&self) -> Self {
+        Self {
+            ip_type: IPType::Broadcast,
+            ..self.last()
+        }
+    }
+    fn class(&self) -> IPClass {
+        IPClass::Unknown
+    }
+    fn is_valid(&self) -> Result<(), IPError> {
+        if self.mask.leading_ones() + self.mask.trailing_zeros() != 128 {
+            return Err(IPError::BadMask(self.mask));
+        }
+        Ok(())
+    }
+    fn num_hosts(&self) -> u32 {
+        let hosts = self.last().ip - self.first().ip;
+        if hosts > u32::MAX as u128 {
+            u32::MAX
+        } else {
+            hosts as u32
+        }
+    }
+}
+
+impl fmt::Display for IPv6 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ip_type == IPType::DHCP {
+            return write!(f, "dhcp");
+        } else if self.ip_type == IPType::Empty {
+            return Ok(());
+        }
+        if self.ip >> 32 == 0xFFFF && self.ip_type != IPType::SubnetMask {
+            // IPv4-mapped addresses keep the IPv4 part dotted (RFC 5952)
+            let v4 = self.ip as u32;
+            let parts: Vec<_> = [24, 16, 8, 0]
+                .iter()
+                .map(|s| (v4 >> s & IP_4_PART).to_string())
+                .collect();
+            write!(f, "::ffff:{}", parts.join("."))?;
+        } else {
+            self.fmt_groups(f)?;
+        }
+        if !f.alternate() && self.ip_type != IPType::SubnetMask {
+            write!(f, "/{}", self.mask_num())?;
+        }
+        Ok(())
+    }
+}
+
+impl IPv6 {
+    /// Writes the groups in hex, with the longest run of zero groups as `::` (RFC 5952)
+    fn fmt_groups(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let groups: Vec<u128> = (0..8)
+            .rev()
+            .map(|i| (self.ip >> (16 * i)) & IP_6_GROUP)
+            .collect();
+        // the longest run of two or more zero groups becomes `::`, the first one on a tie
+        let (mut gap, mut gap_len, mut run) = (0, 0, 0);
+        for (i, g) in groups.iter().enumerate() {
+            run = if *g == 0 { run + 1 } else { 0 };
+            if run > gap_len {
+                gap = i + 1 - run;
+                gap_len = run;
+            }
+        }
+        let hex = |g: &[u128]| {
+            g.iter()
+                .map(|g| format!("{:x}", g))
+                .collect::<Vec<_>>()
+                .join(":")
+        };
+        if gap_len < 2 {
+            write!(f, "{}", hex(&groups))
+        } else {
+            let tail = &groups[gap + gap_len..];
+            write!(f, "{}::{}", hex(&groups[..gap]), hex(tail))
+        }
+    }
+}
+
+/// Which addresses a parameter takes: `\ip`, `\ipv6` or `\ipany`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IPFamily {
+    V4,
+    V6,
+    Any,
+}
+
+/** An address of either family, so one value (and its filters) can hold both.
+
+`\ipany` parameters tell the two apart by whether the text has a `:`.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnyIP {
+    V4(IPv4),
+    V6(IPv6),
+}
+
+impl AnyIP {
+    pub fn parse(ip: &str, family: IPFamily) -> Result<Self, IPError> {
+        match family {
+            IPFamily::V6 => IPv6::parse(ip).map(Self::V6),
+            IPFamily::Any if ip.contains(':') => IPv6::parse(ip).map(Self::V6),
+            IPFamily::V4 | IPFamily::Any => IPv4::parse(ip).map(Self::V4),
+        }
+    }
+    /// An unset address of the family, with `\ipany` starting out as ipv4
+    pub fn empty(family: IPFamily) -> Self {
+        match family {
+            IPFamily::V6 => Self::V6(IPv6::default()),
+            IPFamily::V4 | IPFamily::Any => Self::V4(IPv4::default()),
+        }
+    }
+    /// `ipv4` or `ipv6`
+    pub fn family(&self) -> &'static str {
+        match self {
+            Self::V4(_) => "ipv4",
+            Self::V6(_) => "ipv6",
+        }
+    }
+}
+
+impl Default for AnyIP {
+    fn default() -> Self {
+        Self::V4(IPv4::default())
+    }
+}
+
+/// Calls the same method on either address, rewrapping it if it gives back an address
+macro_rules! either {
+    ($ip:expr, $v:ident => $e:expr) => {
+        match $ip {
+            AnyIP::V4($v) => AnyIP::V4($e),
+            AnyIP::V6($v) => AnyIP::V6($e),
+        }
+    };
+    ($ip:expr, $v:ident -> $e:expr) => {
+        match $ip {
+            AnyIP::V4($v) => $e,
+            AnyIP::V6($v) => $e,
+        }
+    };
+}
+
+impl IP for AnyIP {
+    fn next(&self) -> Option<Self> {
+        match self {
+            Self::V4(ip) => ip.next().map(Self::V4),
+            Self::V6(ip) => ip.next().map(Self::V6),
+        }
+    }
+    fn first(&self) -> Self {
+        either!(self, ip => ip.first())
+    }
+    fn last(&self) -> Self {
+        either!(self, ip => ip.last())
+    }
+    fn ip_type(&self) -> IPType {
+        either!(self, ip -> ip.ip_type())
+    }
+    fn network(&self) -> Self {
+        either!(self, ip => ip.network())
+    }
+    fn subnet_mask(&self) -> Self {
+        either!(self, ip => ip.subnet_mask())
+    }
+    fn wildcard(&self) -> Self {
+        either!(self, ip => ip.wildcard())
+    }
+    fn subnet_addr(&self) -> Self {
+        either!(self, ip => ip.subnet_addr())
+    }
+    fn subnet_net(&self) -> Self {
+        either!(self, ip => ip.subnet_net())
+    }
+    fn subnet_net_num(&self) -> Self {
+        either!(self, ip => ip.subnet_net_num())
+    }
+    fn broadcast(&self) -> Self {
+        either!(self, ip => ip.broadcast())
+    }
+    fn class(&self) -> IPClass {
+        either!(self, ip -> ip.class())
+    }
+    fn is_valid(&self) -> Result<(), IPError> {
+        either!(self, ip -> ip.is_valid())
+    }
+    fn num_hosts(&self) -> u32 {
+        either!(self, ip -> ip.num_hosts())
+    }
+}
+
+impl fmt::Display for AnyIP {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        either!(self, ip -> fmt::Display::fmt(ip, f))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses() {
+        let ip = IPv4::parse("192.168.1.10/24").unwrap();
+        assert_eq!(ip.to_string(), "192.168.1.10/24");
+        assert_eq!(format!("{:#}", ip), "192.168.1.10");
+        assert_eq!(format!("{:#}", ip.subnet_mask()), "255.255.255.0");
+        assert_eq!(IPv4::parse("0.0.0.0/0").unwrap().to_string(), "0.0.0.0/0");
+        assert_eq!(IPv4::parse("dhcp").unwrap().ip_type(), IPType::DHCP);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = |s| IPv4::parse(s).unwrap_err();
+        assert_eq!(err("300.1.1.1/8"), IPError::BadPart("300".to_string()));
+        assert_eq!(err("a.1.1.1/8"), IPError::BadPart("a".to_string()));
+        assert_eq!(err("1.1.1/8"), IPError::MissingPart);
+        assert_eq!(err("1.1.1.1.1/8"), IPError::TooManyParts);
+        assert_eq!(err("1.1.1.1/8/8"), IPError::TooManyParts);
+        assert_eq!(err("1.1.1.1"), IPError::MissingMask);
+        assert_eq!(err("1.1.1.1/33"), IPError::BadMaskLen("33".to_string()));
+        assert_eq!(err("1.1.1.1/x"), IPError::BadMaskLen("x".to_string()));
+        assert_eq!(
+            err("1.1.1/8").to_string(),
+            "expected 4 numbers in the address"
+        );
+    }
+
+    #[test]
+    fn host_range() {
+        let ip = |s| IPv4::parse(s).unwrap();
+        let range = |s| {
+            let ip = ip(s);
+            (format!("{:#}", ip.first()), format!("{:#}", ip.last()))
+        };
+        assert_eq!(
+            range("10.0.0.77/24"),
+            ("10.0.0.1".into(), "10.0.0.254".into())
+        );
+        assert_eq!(range("10.0.0.5/30"), ("10.0.0.5".into(), "10.0.0.6".into()));
+        // point to point links use both addresses, and a /32 is just the host
+        assert_eq!(range("10.0.0.1/31"), ("10.0.0.0".into(), "10.0.0.1".into()));
+        assert_eq!(range("10.0.0.1/32"), ("10.0.0.1".into(), "10.0.0.1".into()));
+        assert_eq!(range("0.0.0.0/32"), ("0.0.0.0".into(), "0.0.0.0".into()));
+        let top = "255.255.255.255".to_string();
+        assert_eq!(range("255.255.255.255/32"), (top.clone(), top.clone()));
+        assert_eq!(
+            range("0.0.0.0/0"),
+            ("0.0.0.1".into(), "255.255.255.254".into())
+        );
+        assert_eq!(format!("{:#}", ip("0.0.0.0/0").broadcast()), top);
+        assert_eq!(ip("255.255.255.255/32").next(), None);
+        assert_eq!(ip("10.0.0.255/24").next(), None);
+    }
+
+    #[test]
+    fn any_ip_picks_the_family() {
+        let v4 = AnyIP::parse("10.1.2.3/24", IPFamily::Any).unwrap();
+        assert_eq!(v4.family(), "ipv4");
+        assert_eq!(format!("{:#}", v4.network()), "10.1.2.0");
+        assert_eq!(format!("{:#}", v4.subnet_mask()), "255.255.255.0");
+        let v6 = AnyIP::parse("2001:db8::1/64", IPFamily::Any).unwrap();
+        assert_eq!(v6.family(), "ipv6");
+        assert_eq!(v6.network().to_string(), "2001:db8::/64");
+        assert_eq!(v6.first().to_string(), "2001:db8::1/64");
+        // a mapped ipv4 address is still written as ipv6
+        let mapped = AnyIP::parse("::ffff:10.1.2.3/128", IPFamily::Any).unwrap();
+        assert_eq!(mapped.family(), "ipv6");
+        assert_eq!(
+            AnyIP::parse("dhcp", IPFamily::Any).unwrap().ip_type(),
+            IPType::DHCP
+        );
+    }
+
+    #[test]
+    fn any_ip_families_are_kept_apart() {
+        assert!(AnyIP::parse("2001:db8::1/64", IPFamily::V4).is_err());
+        assert!(AnyIP::parse("10.1.2.3/24", IPFamily::V6).is_err());
+        assert_eq!(
+            AnyIP::parse("10.1.2.300/24", IPFamily::Any),
+            Err(IPError::BadPart("300".to_string()))
+        );
+        assert!(matches!(AnyIP::empty(IPFamily::V6), AnyIP::V6(_)));
+        assert!(matches!(AnyIP::empty(IPFamily::Any), AnyIP::V4(_)));
+        assert_eq!(AnyIP::empty(IPFamily::Any).ip_type(), IPType::None);
+    }
+
+    fn plan(block: &str, needs: &[(&str, u32)]) -> Result<Vec<Subnet>, IPError> {
+        let needs: Vec<_> = needs.iter().map(|(n, h)| (n.to_string(), *h)).collect();
+        IPv4::parse(block).unwrap().plan(&needs)
+    }
+
+    /// The subnets as `name network`, in the order they were handed out
+    fn nets(subnets: &[Subnet]) -> Vec<String> {
+        subnets
+            .iter()
+            .map(|s| format!("{} {}", s.name, s.net))
+            .collect()
+    }
+
+    #[test]
+    fn plan_largest_first() {
+        let subnets = plan("192.168.0.0/24", &[("wan", 2), ("guest", 50), ("lan", 120)]).unwrap();
+        assert_eq!(
+            nets(&subnets),
+            [
+                "lan 192.168.0.0/25",
+                "guest 192.168.0.128/26",
+                "wan 192.168.0.192/30"
+            ]
+        );
+        // an address inside the block still plans from its network
+        let subnets = plan("10.0.0.77/24", &[("a", 10)]).unwrap();
+        assert_eq!(nets(&subnets), ["a 10.0.0.0/28"]);
+        // a network and broadcast address are always needed
+        let subnets = plan("10.0.0.0/24", &[("one", 1), ("zero", 0), ("exact", 14)]).unwrap();
+        assert_eq!(
+            nets(&subnets),
+            ["exact 10.0.0.0/28", "one 10.0.0.16/30", "zero 10.0.0.20/30"]
+        );
+    }
+
+    #[test]
+    fn plan_keeps_ties_in_order() {
+        let subnets = plan("10.0.0.0/24", &[("b", 20), ("a", 20), ("c", 60), ("d", 20)]).unwrap();
+        assert_eq!(
+            nets(&subnets),
+            [
+                "c 10.0.0.0/26",
+                "b 10.0.0.64/27",
+                "a 10.0.0.96/27",
+                "d 10.0.0.128/27"
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_block_full() {
+        assert_eq!(
+            plan(
+                "192.168.0.0/24",
+                &[("lan", 120), ("guest", 100), ("wan", 2)]
+            ),
+            Err(IPError::BlockFull("wan".to_string(), 2, 260, 256))
+        );
+        // one subnet too big for the whole block
+        assert_eq!(
+            plan("10.0.0.0/28", &[("lan", 20)]),
+            Err(IPError::BlockFull("lan".to_string(), 20, 32, 16))
+        );
+        // filling the block exactly is fine
+        assert_eq!(plan("10.0.0.0/29", &[("a", 2), ("b", 2)]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn plan_gateway_and_first() {
+        let subnets = plan("192.168.0.0/24", &[("lan", 120), ("wan", 2)]).unwrap();
+        let lan = &subnets[0];
+        assert_eq!(lan.gateway().to_string(), "192.168.0.1/25");
+        assert_eq!(lan.first().to_string(), "192.168.0.2/25");
+        assert_eq!(lan.last().to_string(), "192.168.0.126/25");
+        assert_eq!(lan.broadcast().to_string(), "192.168.0.127/25");
+        // a /30 has room for the gateway and one host
+        let wan = &subnets[1];
+        assert_eq!(format!("{:#}", wan.gateway()), "192.168.0.129");
+        assert_eq!(format!("{:#}", wan.first()), "192.168.0.130");
+        assert_eq!(wan.first(), wan.last());
+    }
+
+    fn v6(s: &str) -> IPv6 {
+        IPv6::parse(s).unwrap()
+    }
+
+    #[test]
+    fn ipv6_round_trips() {
+        for s in [
+            "2001:db8:1::1/64",
+            "::/0",
+            "::1/128",
+            "fe80::1/64",
+            "2001:db8::/32",
+            "2001:db8:0:1:1:1:1:1/64",
+            "1:2:3:4:5:6:7:8/128",
+            "::ffff:192.0.2.1/128",
+        ] {
+            assert_eq!(v6(s).to_string(), s);
+        }
+        assert_eq!(format!("{:#}", v6("2001:db8::1/64")), "2001:db8::1");
+        // upper case, leading zeros and uncompressed zeros all come out the same
+        assert_eq!(
+            v6("2001:0DB8:0000:0000:0000:0000:0000:0001/64").to_string(),
+            "2001:db8::1/64"
+        );
+        assert_eq!(v6("::ffff:c000:201/96"), v6("::ffff:192.0.2.1/96"));
+        assert_eq!(
+            v6("64:ff9b::192.0.2.1/96").to_string(),
+            "64:ff9b::c000:201/96"
+        );
+        assert_eq!(IPv6::parse("dhcp").unwrap().ip_type(), IPType::DHCP);
+    }
+
+    #[test]
+    fn ipv6_compression() {
+        // the longest run of zeros is compressed
+        assert_eq!(v6("1:0:0:2:0:0:0:3/64").to_string(), "1:0:0:2::3/64");
+        // the first one when runs tie
+        assert_eq!(v6("1:0:0:2:3:0:0:4/64").to_string(), "1::2:3:0:0:4/64");
+        // and never a single zero group
+        assert_eq!(v6("1:0:2:3:4:5:6:7/64").to_string(), "1:0:2:3:4:5:6:7/64");
+        assert_eq!(v6("0:0:1:2:3:4:5:0/64").to_string(), "::1:2:3:4:5:0/64");
+        assert_eq!(v6("1:2:3:4:5:6::/64").to_string(), "1:2:3:4:5:6::/64");
+    }
+
+    #[test]
+    fn ipv6_parse_errors() {
+        let err = |s| IPv6::parse(s).unwrap_err();
+        assert_eq!(err("2001:db8::1"), IPError::MissingMask);
+        assert_eq!(
+            err("2001:db8::1/129"),
+            IPError::BadMaskLen("129".to_string())
+        );
+        assert_eq!(err("2001:db8::1/64/64"), IPError::TooManyParts);
+        assert_eq!(err("1::2::3/64"), IPError::DoubleGap);
+        assert_eq!(err("1:2:3:4:5:6:7/64"), IPError::GroupCount);
+        assert_eq!(err("1:2:3:4:5:6:7:8:9/64"), IPError::GroupCount);
+        assert_eq!(err("1:2:3:4::5:6:7:8/64"), IPError::GroupCount);
+        assert_eq!(
+            err("2001:db8g::1/64"),
+            IPError::BadGroup("db8g".to_string())
+        );
+        assert_eq!(err("12345::/64"), IPError::BadGroup("12345".to_string()));
+        assert_eq!(
+            err("::ffff:300.0.0.1/96"),
+            IPError::BadPart("300".to_string())
+        );
+        assert_eq!(err("::ffff:1.2.3/96"), IPError::MissingPart);
+        assert_eq!(err("::ffff:1.2.3.4.5/96"), IPError::TooManyParts);
+    }
+
+    #[test]
+    fn ipv6_host_range() {
+        let range = |s: &str| {
+            let ip = v6(s);
+            (format!("{:#}", ip.first()), format!("{:#}", ip.last()))
+        };
+        assert_eq!(
+            range("2001:db8:1::77/64"),
+            (
+                "2001:db8:1::1".into(),
+                "2001:db8:1:0:ffff:ffff:ffff:ffff".into()
+            )
+        );
+        assert_eq!(
+            format!("{}", v6("2001:db8:1::77/64").network()),
+            "2001:db8:1::/64"
+        );
+        // point to point links use both addresses, and a /128 is just the host
+        assert_eq!(
+            range("2001:db8::1/127"),
+            ("2001:db8::".into(), "2001:db8::1".into())
+        );
+        assert_eq!(range("::1/128"), ("::1".into(), "::1".into()));
+        let top = "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff";
+        assert_eq!(range(&format!("{}/128", top)), (top.into(), top.into()));
+        assert_eq!(range("::/0"), ("::1".into(), top.into()));
+        assert_eq!(v6(&format!("{}/128", top)).next(), None);
+        assert_eq!(v6("2001:db8:1:0:ffff:ffff:ffff:ffff/64").next(), None);
+        assert_eq!(v6("::1/128").num_hosts(), 0);
+        assert_eq!(v6("::/0").num_hosts(), u32::MAX);
+    }
+
+    #[test]
+    fn eui64_and_link_local() {
+        let lan = v6("2001:db8:1::/64");
+        assert_eq!(
+            lan.eui64("00:11:22:33:44:55").unwrap().to_string(),
+            "2001:db8:1:0:211:22ff:fe33:4455/64"
+        );
+        // the universal/local bit is flipped, and every mac format works
+        assert_eq!(
+            lan.eui64("0211.2233.4455").unwrap().to_string(),
+            "2001:db8:1:0:11:22ff:fe33:4455/64"
+        );
+        assert_eq!(
+            lan.eui64("aa-bb-cc-dd-ee-ff").unwrap(),
+            lan.eui64("AA:BB:CC:DD:EE:FF").unwrap()
+        );
+        assert_eq!(
+            v6("2001:db8::/48")
+                .eui64("00:11:22:33:44:55")
+                .unwrap()
+                .to_string(),
+            "2001:db8::211:22ff:fe33:4455/48"
+        );
+        assert_eq!(
+            v6("2001:db8::/96").eui64("00:11:22:33:44:55"),
+            Err(IPError::PrefixTooLong(96))
+        );
+        assert_eq!(
+            lan.eui64("00:11:22:33:44"),
+            Err(IPError::BadMac("00:11:22:33:44".to_string()))
+        );
+        assert_eq!(
+            IPv6::link_local("00:11:22:33:44:55").unwrap().to_string(),
+            "fe80::211:22ff:fe33:4455/64"
+        );
+    }
+
+    #[test]
+    fn bad_masks() {
+        let v4 = IPv4 {
+            mask: 0xFF00_FF00,
+            ..IPv4::parse("10.0.0.0/8").unwrap()
+        };
+        assert_eq!(v4.is_valid(), Err(IPError::BadMask(0xFF00_FF00)));
+        // IPv6 masks are reported whole, not cut down to 32 bits
+        let mask = u128::MAX << 64 ^ 1 << 100;
+        let ip = IPv6 {
+            mask,
+            ..v6("2001:db8::/64")
+        };
+        assert_eq!(ip.is_valid(), Err(IPError::BadMask(mask)));
+        assert_eq!(v6("2001:db8::/64").is_valid(), Ok(()));
+        assert_eq!(
+            IPError::BadMask(mask).to_string(),
+            "0xffffffefffffffff0000000000000000 isn't a valid subnet mask"
+        );
+    }
+}
@@ -0,0 +1,19 @@
+#![allow(clippy::result_large_err)]
+
+pub mod config;
+pub mod error;
+pub mod file;
+pub mod interface;
+pub mod ip;
+pub mod lint;
+pub mod output;
+pub mod parse;
+pub mod remote;
+pub mod schema;
+pub mod serial;
+pub mod session;
+pub mod sim;
+pub mod switch;
+pub mod template;
+pub mod values;
+pub mod wizard;
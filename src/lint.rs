@@ -0,0 +1,228 @@
+use crate::error::{ConfError, ErrorKind};
+use crate::parse::{self, Conf, Declared};
+use crate::template::{self, Node, ParamRef};
+
+#[derive(Default)]
+struct Linter {
+    /// What's been declared in each `! for` around the current node, and outside them
+    scopes: Vec<Declared>,
+    ret: Vec<ConfError>,
+}
+
+impl Linter {
+    /// `cond` is set for an `! if`, which can leave the type to other uses
+    fn param(&mut self, r: &ParamRef, n: usize, file: Option<&str>, cond: bool) {
+        let depth = self.scopes.len() - 1;
+        match Conf::declare_at(r, n, depth) {
+            Ok(Some(_)) => (),
+            // loop variables don't have a type to conflict
+            Ok(None) => return,
+            Err(e) => return self.ret.push(e.in_file(file)),
+        }
+        if cond && r.ty.is_none() && r.default.is_none() {
+            return;
+        }
+        let at = parse::location(n, file);
+        let default = r.default.as_ref();
+        if let Err(kind) = self.scopes[depth - r.up].check(&r.name, parse::ref_kind(r), default, at)
+        {
+            let e = ConfError::new(kind).at(n, r.column).param(&r.name);
+            self.ret.push(e.in_file(file));
+        }
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            match node {
+                Node::Line(l) => {
+                    if l.optional && l.params().next().is_none() {
+                        self.ret
+                            .push(l.error(ErrorKind::NoParams, l.indent.len() + 1));
+                    }
+                    for r in l.params() {
+                        self.param(r, l.number, l.file.as_deref(), false);
+                    }
+                }
+                Node::For {
+                    line,
+                    file,
+                    name,
+                    body,
+                } => {
+                    let scope = self.scopes.len() - 1;
+                    let at = parse::location(*line, file.as_deref());
+                    let inner = match self.scopes[scope].take_list(name, at) {
+                        Ok(inner) => inner,
+                        Err(kind) => {
+                            let e = ConfError::new(kind).at(*line, 1).param(name);
+                            self.ret.push(e.in_file(file.as_deref()));
+                            Declared::default()
+                        }
+                    };
+                    self.scopes.push(inner);
+                    self.nodes(body);
+                    let inner = self.scopes.pop().unwrap_or_default();
+                    self.scopes[scope].put_list(name, inner);
+                }
+                Node::If {
+                    line,
+                    file,
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    self.param(&cond.param, *line, file.as_deref(), true);
+                    self.nodes(then);
+                    self.nodes(otherwise);
+                }
+                Node::Section { body, .. } => self.nodes(body),
+                Node::Comment(..) => (),
+            }
+        }
+    }
+}
+
+/**
+ * Checks a template for likely mistakes, returning every one found
+ *
+ * Besides anything that stops the template parsing, that's a `! for` that
+ * isn't closed or has no `$list`, bad types, ranges and filters, a
+ * parameter used with two different types, and `?\` lines without
+ * parameters. An empty list means the template looks fine.
+ */
+pub fn lint(file: &str) -> Vec<ConfError> {
+    let (nodes, warnings) = match template::load_with_warnings(file) {
+        Ok(n) => n,
+        Err(e) => return vec![e],
+    };
+    let mut linter = Linter {
+        scopes: vec![Declared::default()],
+        ..Linter::default()
+    };
+    linter.nodes(&nodes);
+    let mut ret: Vec<_> = warnings
+        .into_iter()
+        .chain(linter.ret.into_iter().map(|e| e.or_file(file)))
+        .collect();
+    ret.sort_by_key(|e| (e.file.clone(), e.line, e.column));
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The line and kind of each problem in `tests/data/lint/<name>.conf`
+    fn problems(name: &str) -> Vec<(Option<usize>, ErrorKind)> {
+        let file = format!("tests/data/lint/{}.conf", name);
+        lint(&file)
+            .into_iter()
+            .inspect(|e| assert_eq!(e.file.as_deref(), Some(&file[..])))
+            .map(|e| (e.line, e.kind))
+            .collect()
+    }
+
+    fn unbalanced(m: &str) -> ErrorKind {
+        ErrorKind::Unbalanced(m.to_string())
+    }
+
+    #[test]
+    fn unbalanced_for() {
+        assert_eq!(
+            problems("unclosed"),
+            [(Some(3), unbalanced("`! for` is never closed"))]
+        );
+        assert_eq!(
+            problems("stray-end"),
+            [(
+                Some(3),
+                unbalanced("`! end for` without a `! for` ends the template")
+            )]
+        );
+    }
+
+    #[test]
+    fn for_without_dollar() {
+        // the `! end for` then has nothing to close
+        assert_eq!(
+            problems("no-dollar"),
+            [
+                (Some(3), ErrorKind::BadFor),
+                (
+                    Some(5),
+                    unbalanced("`! end for` without a `! for` ends the template")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_type() {
+        assert_eq!(
+            problems("bad-type"),
+            [(Some(2), ErrorKind::UnknownType("\\host".to_string()))]
+        );
+    }
+
+    #[test]
+    fn bad_range() {
+        assert_eq!(
+            problems("bad-range"),
+            [(Some(3), ErrorKind::BadRange("0..".to_string()))]
+        );
+    }
+
+    #[test]
+    fn conflicting_types() {
+        let kind = ErrorKind::ConflictingType(
+            "`{0..10}`".to_string(),
+            "`{\\ip}`".to_string(),
+            "line 2".to_string(),
+        );
+        assert_eq!(problems("conflict"), [(Some(3), kind)]);
+    }
+
+    #[test]
+    fn plain_uses_take_the_type() {
+        assert!(lint("tests/data/declare/merged.conf").is_empty());
+        let problems: Vec<_> = lint("tests/data/declare/list.conf")
+            .into_iter()
+            .map(|e| (e.line, e.kind))
+            .collect();
+        let kind = ErrorKind::ConflictingType(
+            "a `! for` list".to_string(),
+            "plain text".to_string(),
+            "line 2".to_string(),
+        );
+        assert_eq!(problems, [(Some(3), kind)]);
+    }
+
+    #[test]
+    fn optional_line_without_params() {
+        assert_eq!(problems("no-params"), [(Some(3), ErrorKind::NoParams)]);
+    }
+
+    #[test]
+    fn several_problems() {
+        let kind = ErrorKind::ConflictingType(
+            "`{\\ip}`".to_string(),
+            "`{\\interface}`".to_string(),
+            "line 5".to_string(),
+        );
+        assert_eq!(
+            problems("several"),
+            [
+                (Some(3), ErrorKind::NoParams),
+                (Some(4), ErrorKind::BadFor),
+                (Some(6), kind),
+                (Some(7), unbalanced("`! for` is never closed")),
+            ]
+        );
+    }
+
+    #[test]
+    fn sample_templates_are_clean() {
+        assert!(lint("router.conf").is_empty());
+        assert!(lint("switch.conf").is_empty());
+    }
+}
@@ -1,31 +1,156 @@
+use crate::parse::Conf;
+use crate::remote;
+use crate::serial;
+use crate::session::{Credentials, SessionError};
 use std::io::*;
 use std::process::*;
+use std::time::Duration;
 
-pub trait WriteProc {
-    fn write_to_clip(self);
-    fn write_to_minicom(self);
+/// Where a compiled config should be sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    Stdout,
+    Clip,
+    Minicom,
+    File(String),
+    /// Straight to a device console, waiting for the prompt after each line
+    Serial {
+        device: String,
+        baud: u32,
+        login: Credentials,
+    },
+    /// Logs in over telnet, `host` may include a port
+    Telnet {
+        host: String,
+        login: Credentials,
+    },
+    /// Logs in through the system `ssh` client, `host` may include a port
+    Ssh {
+        host: String,
+        login: Credentials,
+    },
 }
 
-impl WriteProc for Vec<String> {
-    fn write_to_clip(self) {
-        // spawn process
-        let p = std::process::Command::new("xclip").args(vec!["-selection", "c", "-i"]).stdin(Stdio::piped()).spawn().unwrap();
-        let mut outstdin = p.stdin.unwrap();
-        let mut writer = BufWriter::new(&mut outstdin);
-        for l in self {
-            writer.write(l.as_bytes()).unwrap();
-            writer.write("\n".as_bytes()).unwrap();
+impl Output {
+    pub fn write(self, lines: Vec<String>) -> std::result::Result<(), SessionError> {
+        match self {
+            Self::Stdout => Ok(lines.write_to_stdout()?),
+            Self::Clip => Ok(lines.write_to_clip()?),
+            Self::Minicom => Ok(lines.write_to_minicom()?),
+            Self::File(f) => Ok(lines.write_to_file(&f)?),
+            Self::Serial {
+                device,
+                baud,
+                login,
+            } => lines.write_to_serial(&device, baud, &login),
+            Self::Telnet { host, login } => lines.write_to_telnet(&host, &login),
+            Self::Ssh { host, login } => lines.write_to_ssh(&host, &login),
         }
     }
 
-    fn write_to_minicom(self) {
-        // spawn process
-        let p = std::process::Command::new("minicom").arg("cisco").stdin(Stdio::piped()).spawn().unwrap();
-        let mut outstdin = p.stdin.unwrap();
-        let mut writer = BufWriter::new(&mut outstdin);
-        for l in self {
-            writer.write(l.as_bytes()).unwrap();
-            writer.write("\n".as_bytes()).unwrap();
+    /// Fills in login details that weren't given from the template's values
+    pub fn login_from(self, conf: &Conf) -> Self {
+        match self {
+            Self::Serial {
+                device,
+                baud,
+                login,
+            } => Self::Serial {
+                device,
+                baud,
+                login: login.or_from(conf),
+            },
+            Self::Telnet { host, login } => Self::Telnet {
+                host,
+                login: login.or_from(conf),
+            },
+            Self::Ssh { host, login } => Self::Ssh {
+                host,
+                login: login.or_from(conf),
+            },
+            o => o,
         }
     }
-}
\ No newline at end of file
+}
+
+pub trait WriteProc {
+    fn write_to_clip(self) -> Result<()>;
+    fn write_to_minicom(self) -> Result<()>;
+    fn write_to_stdout(self) -> Result<()>;
+    fn write_to_file(self, name: &str) -> Result<()>;
+    fn write_to_serial(
+        self,
+        device: &str,
+        baud: u32,
+        login: &Credentials,
+    ) -> std::result::Result<(), SessionError>;
+    fn write_to_telnet(
+        self,
+        host: &str,
+        login: &Credentials,
+    ) -> std::result::Result<(), SessionError>;
+    fn write_to_ssh(self, host: &str, login: &Credentials)
+        -> std::result::Result<(), SessionError>;
+}
+
+fn write_lines(lines: Vec<String>, out: impl Write) -> Result<()> {
+    let mut writer = BufWriter::new(out);
+    for l in lines {
+        writer.write_all(l.as_bytes())?;
+        writer.write_all("\n".as_bytes())?;
+    }
+    writer.flush()
+}
+
+fn write_to_proc(lines: Vec<String>, cmd: &mut Command) -> Result<()> {
+    // spawn process
+    let mut p = cmd.stdin(Stdio::piped()).spawn()?;
+    if let Some(outstdin) = p.stdin.take() {
+        write_lines(lines, outstdin)?;
+    }
+    p.wait()?;
+    Ok(())
+}
+
+impl WriteProc for Vec<String> {
+    fn write_to_clip(self) -> Result<()> {
+        write_to_proc(self, Command::new("xclip").args(["-selection", "c", "-i"]))
+    }
+
+    fn write_to_minicom(self) -> Result<()> {
+        write_to_proc(self, Command::new("minicom").arg("cisco"))
+    }
+
+    fn write_to_stdout(self) -> Result<()> {
+        write_lines(self, stdout().lock())
+    }
+
+    fn write_to_file(self, name: &str) -> Result<()> {
+        write_lines(self, std::fs::File::create(name)?)
+    }
+
+    fn write_to_serial(
+        self,
+        device: &str,
+        baud: u32,
+        login: &Credentials,
+    ) -> std::result::Result<(), SessionError> {
+        serial::write_lines(device, baud, login, Duration::from_secs(30), &self)
+    }
+
+    fn write_to_telnet(
+        self,
+        host: &str,
+        login: &Credentials,
+    ) -> std::result::Result<(), SessionError> {
+        remote::write_telnet(host, login, Duration::from_secs(30), &self)
+    }
+
+    fn write_to_ssh(
+        self,
+        host: &str,
+        login: &Credentials,
+    ) -> std::result::Result<(), SessionError> {
+        remote::write_ssh(host, login, Duration::from_secs(30), &self)
+    }
+}
@@ -1,396 +1,1088 @@
-use crate::file::File;
+use crate::error::{ConfError, ErrorKind};
 use crate::interface::*;
 use crate::ip::*;
+use crate::template::{self, Cond, Line, Node, ParamRef, Part};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::ops::*;
 use std::default::Default;
+use std::ops::*;
 
 #[derive(Debug, Clone)]
-enum Value {
+pub(crate) enum Value {
     Simple(String, bool),
     Selected(String, Vec<String>, bool),
     Range(isize, Range<isize>, bool),
-    IP(IPv4, bool),
+    /// The address, and which family the parameter takes
+    IP(AnyIP, IPFamily, bool),
     Interface(Iface, bool),
-    List(Vec<HashMap<String, Value>>),
-}
-
-pub struct Conf {
-    vals: HashMap<String, Value>,
-    conf: Vec<String>,
+    /// The empty entry new ones are copied from, then the entries
+    List(HashMap<String, Value>, Vec<HashMap<String, Value>>),
 }
 
-impl Conf {
-    fn parse_line(mut tmp: &str, ret: &mut HashMap<String, Value>) {
-        // `?\` is an optional command, ignored for now
-        if tmp.starts_with("?\\") {
-            tmp = &tmp[2..];
-        }
-        for w in tmp.split_whitespace() {
-            let word = w.trim_matches('"');
-            let optional = if word.starts_with("$") {
-                // `$` is a required parameter
-                false
-            } else if word.starts_with("?") {
-                // `?` is an optional parameter
-                true
-            } else {
-                continue;
-            };
-            let mut parts = word
-                .split(|c| c == '$' || c == '?' || c == '{' || c == '}')
-                .filter(|&s| s != "");
-            let name = parts.next().unwrap().split("-").next().unwrap().to_string();
-            if let Some(type_name) = parts.next() {
-                // `{}` specifies that the parameter only accepts the values listed
-                //   Only for sanity checks and error checking. Not needed, but nice to have
-                //   Comma seperated for word vals, rust range syntax for nums
-                //   \ip: anything that starts with a \ is a special type (e.g. ip, mask, interface)
-                //   $ip-mask refers to the mask part of the ip param, -ends key parse
-                if type_name.starts_with("\\") {
-                    match type_name {
-                        "\\ip" | "\\ipslash" => {
-                            ret.insert(name, Value::IP(IPv4::default(), optional));
-                        }
-                        "\\interface" => {
-                            ret.insert(name, Value::Interface(Iface::default(), optional));
-                        }
-                        "\\mac" => {
-                            ret.insert(name, Value::Simple(String::default(), optional));
-                        }
-                        _ => panic!("{} isn't a valid type", type_name),
-                    }
-                } else if type_name.contains("..") {
-                    let mut range_parts = type_name.split("..");
-                    let start = range_parts
-                        .next()
-                        .expect("Num Error")
-                        .parse()
-                        .expect("Num Error");
-                    let end = range_parts
-                        .next()
-                        .expect("Num Error")
-                        .parse()
-                        .expect("Num Error");
-                    ret.insert(name, Value::Range(-1, start..end, optional));
-                } else {
-                    ret.insert(
-                        name,
-                        Value::Selected(
-                            String::default(),
-                            type_name.split(",").map(|s| s.to_string()).collect(),
-                            optional,
-                        ),
-                    );
-                }
-            } else {
-                ret.insert(name, Value::Simple(String::default(), optional));
-            }
+impl Value {
+    /// Whether a value has been supplied (lists always count as set)
+    pub(crate) fn is_set(&self) -> bool {
+        match self {
+            Value::Simple(s, _) | Value::Selected(s, _, _) => !s.is_empty(),
+            Value::Range(i, _, _) => *i != -1,
+            Value::IP(ip, _, _) => ip.ip_type() != IPType::None,
+            Value::Interface(it, _) => !it.is_none(),
+            Value::List(..) => true,
         }
     }
-    fn parse_pattern(i: &mut std::slice::Iter<String>, ret: &mut HashMap<String, Value>) {
-        // let mut i = v.iter();
-        while let Some(s) = i.next() {
-            let tmp: &str = s.trim();
-            // `!` is ignored, excpet when parsing for loops
-            if tmp.starts_with("!") || tmp.starts_with("#") {
-                if tmp.contains("! for $") || tmp.contains("# for $") {
-                    let name = tmp[7..].split_whitespace().next().expect("").to_string();
-                    let mut hash = HashMap::new();
-                    Self::parse_pattern(i, &mut hash);
-                    ret.insert(name, Value::List(vec![hash]));
-                } else if tmp.contains("! end for")
-                    || tmp.contains("! end conf")
-                    || tmp.contains("# end for")
-                    || tmp.contains("# end conf")
-                {
-                    return;
-                }
-            } else {
-                Self::parse_line(tmp, ret);
-            }
+
+    /// Whether the parameter was declared with `?` (lists can always be empty)
+    pub(crate) fn is_optional(&self) -> bool {
+        match self {
+            Value::Simple(_, o)
+            | Value::Selected(_, _, o)
+            | Value::Range(_, _, o)
+            | Value::IP(_, _, o)
+            | Value::Interface(_, o) => *o,
+            Value::List(..) => true,
         }
     }
-    pub fn parse(file: &str) -> Self {
-        let v: Vec<String> = File::read(file).expect("File ran into issue").collect();
-        let mut h = HashMap::new();
-        Self::parse_pattern(&mut v.iter(), &mut h);
-        Self { vals: h, conf: v }
+
+    fn set_optional(&mut self, optional: bool) {
+        match self {
+            Value::Simple(_, o)
+            | Value::Selected(_, _, o)
+            | Value::Range(_, _, o)
+            | Value::IP(_, _, o)
+            | Value::Interface(_, o) => *o = optional,
+            Value::List(..) => (),
+        }
     }
-    fn set_value(hash: &mut HashMap<String, Value>, key: String, value: String) {
-        match hash.get_mut(&key).expect("Key not valid") {
+
+    /// Checks a value against the type and stores it
+    pub(crate) fn set(&mut self, value: String) -> Result<(), ErrorKind> {
+        match self {
             Value::Simple(s, _o) => *s = value,
             Value::Selected(s, l, _o) => {
                 if l.contains(&value) {
                     *s = value
                 } else {
-                    panic!("{} is not valid for {}", value, key)
+                    return Err(ErrorKind::InvalidChoice(value, l.clone()));
                 }
             }
             Value::Range(i, r, _o) => {
-                if r.contains(&value.parse().expect("Expected Number")) {
-                    *i = value.parse().unwrap()
+                let num = value
+                    .parse()
+                    .map_err(|_| ErrorKind::NotANumber(value.clone()))?;
+                if r.contains(&num) {
+                    *i = num
                 } else {
-                    panic!("{} is not valid for {}", value, key)
+                    return Err(ErrorKind::OutOfRange(num, r.clone()));
                 }
             }
-            Value::IP(ip, _o) => *ip = IPv4::parse(&value),
-            Value::Interface(it, _o) => *it = Iface::parse(&value),
-            _ => (),
+            Value::IP(ip, family, _o) => {
+                *ip = AnyIP::parse(&value, *family).map_err(ErrorKind::BadIP)?
+            }
+            Value::Interface(it, _o) => {
+                *it = Iface::parse(&value).map_err(ErrorKind::BadInterface)?
+            }
+            Value::List(..) => return Err(ErrorKind::IsList),
         }
+        Ok(())
     }
-    pub fn set(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
-        Self::set_value(&mut self.vals, key.into(), value.into());
-        self
+}
+
+#[derive(Clone)]
+pub struct Conf {
+    pub(crate) vals: HashMap<String, Value>,
+    pub(crate) nodes: Vec<Node>,
+    file: String,
+}
+
+/// The parameters of a template, or of one list entry
+type Entry = HashMap<String, Value>;
+
+/// Gets the empty entry and the entries of a `! for` list
+fn list_mut<'a>(
+    hash: &'a mut Entry,
+    key: &str,
+) -> Result<(&'a Entry, &'a mut Vec<Entry>), ConfError> {
+    match hash.get_mut(key) {
+        Some(Value::List(proto, entries)) => Ok((proto, entries)),
+        Some(_) => Err(ConfError::new(ErrorKind::NotAList).param(key)),
+        None => Err(ConfError::new(ErrorKind::UnknownParam).param(key)),
     }
-    pub fn set_present(mut self, key: impl Into<String>) -> Self {
-        match self.vals.get_mut(&key.into()).expect("Key not valid") {
-            Value::Selected(s, l, _b) => {
-                if l.len() == 1 {
-                    *s = l[0].to_string()
-                } else {
-                    panic!("Key has too many options")
-                }
+}
+
+/// Follows `path` down to a list entry, adding `list[n].` to `name` for each step
+fn entry_mut<'a>(
+    hash: &'a mut HashMap<String, Value>,
+    path: &[(&str, usize)],
+    name: &mut String,
+) -> Result<&'a mut HashMap<String, Value>, ConfError> {
+    let mut cur = hash;
+    for (k, n) in path {
+        let full = format!("{}{}", name, k);
+        let (_, entries) = list_mut(cur, k).map_err(|e| e.param(&full))?;
+        cur = entries
+            .get_mut(*n)
+            .ok_or_else(|| ConfError::new(ErrorKind::NoEntry(*n)).param(&full))?;
+        *name += &format!("{}[{}].", k, n);
+    }
+    Ok(cur)
+}
+
+/// Follows `path` down to a list entry, if it exists
+fn entry<'a>(
+    hash: &'a HashMap<String, Value>,
+    path: &[(&str, usize)],
+) -> Option<&'a HashMap<String, Value>> {
+    path.iter()
+        .try_fold(hash, |cur, (k, n)| match cur.get(*k)? {
+            Value::List(_, entries) => entries.get(*n),
+            _ => None,
+        })
+}
+
+/// Looks `key` up in one scope: the top level, or a single list entry
+fn get_in(hash: &HashMap<String, Value>, key: &str) -> Option<String> {
+    match hash.get(key)? {
+        v if !v.is_set() => None,
+        Value::Simple(s, _) | Value::Selected(s, _, _) => Some(s.clone()),
+        Value::Range(i, _, _) => Some(i.to_string()),
+        Value::IP(ip @ AnyIP::V6(_), _, _) => Some(ip.to_string()),
+        Value::IP(ip, _, _) => Some(format!("{:#}", ip)),
+        Value::Interface(it, _) => Some(it.fmt()),
+        Value::List(..) => None,
+    }
+}
+
+/// Makes a value of the type a reference declares, holding its default if it has one
+fn declare(r: &ParamRef, n: usize) -> Result<Value, ConfError> {
+    let mut val = declare_type(r, n)?;
+    if let Some(d) = &r.default {
+        val.set(d.clone())
+            .map_err(|kind| ConfError::new(kind).at(n, r.column).param(&r.name))?;
+    }
+    Ok(val)
+}
+
+fn ip_value(family: IPFamily, optional: bool) -> Value {
+    Value::IP(AnyIP::empty(family), family, optional)
+}
+
+/// Makes an empty value of the type a reference declares
+fn declare_type(r: &ParamRef, n: usize) -> Result<Value, ConfError> {
+    let optional = r.optional;
+    let err = |kind| ConfError::new(kind).at(n, r.column).param(&r.name);
+    // `{}` specifies that the parameter only accepts the values listed
+    //   Only for sanity checks and error checking. Not needed, but nice to have
+    //   Comma seperated for word vals, rust range syntax for nums
+    //   \ip: anything that starts with a \ is a special type (e.g. ip, mask, interface)
+    // `|filter` changes the value on the way out, see `filter`
+    let is_ip = matches!(
+        r.ty.as_deref(),
+        Some("\\ip") | Some("\\ipslash") | Some("\\ipv6") | Some("\\ipany")
+    );
+    let mut text: Option<&str> = None;
+    for f in &r.filters {
+        let (name, arg) = match f.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (&f[..], None),
+        };
+        let known = [&IP_FILTERS[..], &IPV6_FILTERS, &MAC_FILTERS, &TEXT_FILTERS]
+            .iter()
+            .any(|fs| fs.contains(&name));
+        if !known || arg.is_some() && !IPV6_FILTERS.contains(&name) {
+            return Err(err(ErrorKind::UnknownFilter(f.clone())));
+        }
+        let only = |ty: &str| err(ErrorKind::FilterType(name.to_string(), ty.to_string()));
+        if IPV6_FILTERS.contains(&name) {
+            if r.ty.as_deref() != Some("\\ipv6") {
+                return Err(only("\\ipv6"));
+            } else if arg.is_none() {
+                return Err(err(ErrorKind::FilterParam(name.to_string())));
             }
-            _ => panic!("Key doesn't have options"),
+        } else if MAC_FILTERS.contains(&name) && r.ty.as_deref() != Some("\\mac") {
+            return Err(only("\\mac"));
+        } else if !IP_FILTERS.contains(&name) {
+            text = text.or(Some(name));
+            continue;
+        } else if !is_ip {
+            return Err(err(ErrorKind::NotAnIP(f.clone())));
         }
-        self
+        if let Some(t) = text {
+            return Err(err(ErrorKind::FilterAfterText(
+                name.to_string(),
+                t.to_string(),
+            )));
+        }
+        // `family` writes the address out as `ipv4` or `ipv6`
+        if name == "family" {
+            text = Some(name);
+        }
+    }
+    let type_name = match &r.ty {
+        Some(t) => t,
+        None => return Ok(Value::Simple(String::default(), optional)),
+    };
+    if type_name.starts_with("\\") {
+        match &type_name[..] {
+            "\\ip" | "\\ipslash" => Ok(ip_value(IPFamily::V4, optional)),
+            "\\ipv6" => Ok(ip_value(IPFamily::V6, optional)),
+            "\\ipany" => Ok(ip_value(IPFamily::Any, optional)),
+            "\\interface" => Ok(Value::Interface(Iface::default(), optional)),
+            "\\mac" => Ok(Value::Simple(String::default(), optional)),
+            _ => Err(err(ErrorKind::UnknownType(type_name.to_string()))),
+        }
+    } else if type_name.contains("..") {
+        let bad_range = || err(ErrorKind::BadRange(type_name.to_string()));
+        let mut range_parts = type_name.split("..").map(|p| p.parse());
+        let start = range_parts
+            .next()
+            .and_then(|p| p.ok())
+            .ok_or_else(bad_range)?;
+        let end = range_parts
+            .next()
+            .and_then(|p| p.ok())
+            .ok_or_else(bad_range)?;
+        Ok(Value::Range(-1, start..end, optional))
+    } else {
+        Ok(Value::Selected(
+            String::default(),
+            type_name.split(",").map(|s| s.to_string()).collect(),
+            optional,
+        ))
+    }
+}
+
+/// Filters that only work on addresses, all but `family` working out another address
+const IP_FILTERS: [&str; 7] = [
+    "mask",
+    "wildcard",
+    "network",
+    "first",
+    "last",
+    "broadcast",
+    "family",
+];
+/// Filters that only work on an `\ipv6`, and take a parameter, e.g. `|eui64:mac`
+const IPV6_FILTERS: [&str; 1] = ["eui64"];
+/// Filters that turn a `\mac` into an address, written as text
+const MAC_FILTERS: [&str; 1] = ["link_local"];
+const TEXT_FILTERS: [&str; 2] = ["upper", "lower"];
+
+/// Applies an address filter, or `None` if `name` only works on text
+fn ip_filter<T: IP>(name: &str, ip: &T) -> Option<Result<T, ErrorKind>> {
+    // `dhcp` only has a mask, which is left empty
+    if ip.ip_type() == IPType::DHCP
+        && !["mask", "family"].contains(&name)
+        && IP_FILTERS.contains(&name)
+    {
+        return Some(Err(ErrorKind::NoAddress(name.to_string())));
+    }
+    Some(Ok(match name {
+        "mask" => ip.subnet_mask(),
+        "wildcard" => ip.wildcard(),
+        "network" => ip.network(),
+        "first" => ip.first(),
+        "last" => ip.last(),
+        "broadcast" => ip.broadcast(),
+        _ => return None,
+    }))
+}
+
+/// Formats a value as it goes into the config, or `None` if it isn't set
+///
+/// A filter's parameter that isn't set, e.g. the `mac` in `|eui64:mac`, leaves the value unset too.
+fn render(r: &ParamRef, val: &Value, scope: &Scope) -> Result<Option<String>, ErrorKind> {
+    if !val.is_set() {
+        return Ok(None);
+    }
+    let mut args = Vec::new();
+    for a in r.filter_args() {
+        match render(&a, &*scope.get(&a)?, scope)? {
+            Some(s) => args.push(s),
+            None => return Ok(None),
+        }
+    }
+    let text = match val {
+        Value::Simple(s, _) | Value::Selected(s, _, _) => s.clone(),
+        Value::Range(i, _, _) => i.to_string(),
+        Value::IP(ip, _, _) => {
+            // only `|eui64` takes a parameter, so the args line up with it
+            let mut args = args.into_iter();
+            let eui64 = |f: &str, ip: &AnyIP| {
+                let (AnyIP::V6(v6), true) = (ip, f.starts_with("eui64:")) else {
+                    return None;
+                };
+                let mac = args.next()?;
+                Some(match ip.ip_type() {
+                    IPType::DHCP => Err(ErrorKind::NoAddress("eui64".to_string())),
+                    _ => v6.eui64(&mac).map(AnyIP::V6).map_err(ErrorKind::BadIP),
+                })
+            };
+            return render_ip(r, *ip, eui64).map(Some);
+        }
+        Value::Interface(it, _) => it.fmt(),
+        Value::List(..) => return Err(ErrorKind::IsList),
+    };
+    text_filters(text, r.filters.iter()).map(Some)
+}
+
+/// Addresses stay addresses until a text filter (or `family`) needs them written out
+///
+/// `extra` applies the filters only some addresses have, like `|eui64`.
+fn render_ip(
+    r: &ParamRef,
+    mut ip: AnyIP,
+    mut extra: impl FnMut(&str, &AnyIP) -> Option<Result<AnyIP, ErrorKind>>,
+) -> Result<String, ErrorKind> {
+    let mut filters = r.filters.iter().peekable();
+    while let Some(new) = filters
+        .peek()
+        .and_then(|f| ip_filter(f, &ip).or_else(|| extra(f, &ip)))
+    {
+        ip = new?;
+        filters.next();
     }
-    pub fn add_list(mut self, key: impl Into<String>) -> Self {
-        match self.vals.get_mut(&key.into()).expect("Key doesn't exist") {
-            Value::List(v) => {
-                v.push(v[0].clone());
+    // ipv6 addresses are always written with their prefix length
+    let slash = matches!(ip, AnyIP::V6(_)) || r.ty.as_deref() == Some("\\ipslash");
+    let text = match filters.peek() {
+        Some(f) if *f == "family" => {
+            filters.next();
+            ip.family().to_string()
+        }
+        _ if slash => format!("{}", ip),
+        _ => format!("{:#}", ip),
+    };
+    text_filters(text, filters)
+}
+
+fn text_filters<'a>(
+    mut text: String,
+    filters: impl Iterator<Item = &'a String>,
+) -> Result<String, ErrorKind> {
+    for f in filters {
+        text = match &f[..] {
+            "upper" => text.to_uppercase(),
+            "lower" => text.to_lowercase(),
+            "link_local" => format!("{:#}", IPv6::link_local(&text).map_err(ErrorKind::BadIP)?),
+            f if IP_FILTERS.contains(&f) => return Err(ErrorKind::NotAnIP(f.to_string())),
+            f => return Err(ErrorKind::UnknownFilter(f.to_string())),
+        };
+    }
+    Ok(text)
+}
+
+/// What a typed reference declares, with `\ipslash` the same as `\ip` since it holds the same value
+pub(crate) fn ref_kind(r: &ParamRef) -> Option<String> {
+    Some(match r.ty.as_deref()? {
+        "\\ipslash" => "`{\\ip}`".to_string(),
+        t => format!("`{{{}}}`", t),
+    })
+}
+
+const LIST_KIND: &str = "a `! for` list";
+
+/// How a parameter was declared
+struct Decl {
+    /// e.g. `{\ip}`, from `ref_kind`, or `None` while it's only been used plain
+    kind: Option<String>,
+    default: Option<String>,
+    /// Where the kind came from, or the first use while there isn't one, e.g. `line 3`
+    at: String,
+}
+
+/// The parameters declared in a scope so far, to check the other uses against
+#[derive(Default)]
+pub(crate) struct Declared {
+    params: HashMap<String, Decl>,
+    /// What each `! for` list's body declared, so a second `! for` of it agrees
+    lists: HashMap<String, Declared>,
+}
+
+impl Declared {
+    /**
+     * Records a use of a parameter, returning whether it decides the type
+     *
+     * That's the first use, or the first typed one after plain ones. A plain
+     * use takes whatever type the others give, so only two typed uses can
+     * disagree, or a plain use of a `! for` list. Any defaults have to match.
+     */
+    pub(crate) fn check(
+        &mut self,
+        name: &str,
+        kind: Option<String>,
+        default: Option<&String>,
+        at: String,
+    ) -> Result<bool, ErrorKind> {
+        let first = match self.params.get_mut(name) {
+            Some(first) => first,
+            None => {
+                let default = default.cloned();
+                self.params
+                    .insert(name.to_string(), Decl { kind, default, at });
+                return Ok(true);
+            }
+        };
+        let describe = |kind: &Option<String>, default: Option<&String>| {
+            let kind = kind.as_deref().unwrap_or("plain text");
+            match default {
+                Some(d) => format!("{} with default `{}`", kind, d),
+                None => kind.to_string(),
             }
-            _ => panic!("Key isn't a list"),
+        };
+        let kinds_differ = match (&first.kind, &kind) {
+            (Some(a), Some(b)) => a != b,
+            (Some(k), None) | (None, Some(k)) => k == LIST_KIND,
+            (None, None) => false,
+        };
+        let defaults_differ = matches!((&first.default, default), (Some(a), Some(b)) if a != b);
+        if kinds_differ || defaults_differ {
+            return Err(ErrorKind::ConflictingType(
+                describe(&kind, default),
+                describe(&first.kind, first.default.as_ref()),
+                first.at.clone(),
+            ));
         }
-        self
+        if first.default.is_none() {
+            first.default = default.cloned();
+        }
+        if first.kind.is_none() && kind.is_some() {
+            first.kind = kind;
+            first.at = at;
+            return Ok(true);
+        }
+        Ok(false)
     }
-    pub fn add_list_count(mut self, key: impl Into<String>, num: usize) -> Self {
-        let key_s = key.into();
-        for _ in 0..num {
-            match self.vals.get_mut(&key_s).expect("Key doesn't exist") {
-                Value::List(v) => {
-                    v.push(v[0].clone());
-                }
-                _ => panic!("Key isn't a list"),
+
+    /// Checks `name` can be a `! for` list, and takes what earlier `! for`s of it declared
+    pub(crate) fn take_list(&mut self, name: &str, at: String) -> Result<Declared, ErrorKind> {
+        self.check(name, Some(LIST_KIND.to_string()), None, at)?;
+        Ok(self.lists.remove(name).unwrap_or_default())
+    }
+
+    /// Puts back what a `! for` body declared
+    pub(crate) fn put_list(&mut self, name: &str, list: Declared) {
+        self.lists.insert(name.to_string(), list);
+    }
+}
+
+/// Where a use of a parameter is, e.g. `line 3` or `base.conf:3`
+pub(crate) fn location(line: usize, file: Option<&str>) -> String {
+    match file {
+        Some(f) => format!("{}:{}", f, line),
+        None => format!("line {}", line),
+    }
+}
+
+/// A use of a parameter, on its way to the scope it belongs to
+struct Use {
+    r: ParamRef,
+    val: Value,
+    /// From an untyped `! if`, so it only declares the parameter if nothing else does
+    weak: bool,
+    line: usize,
+    file: Option<String>,
+}
+
+/// Variables every `! for` body has, e.g. `$@index`
+const LOOP_VARS: [&str; 4] = ["@index", "@count", "@first", "@last"];
+
+fn not_in_loop(r: &ParamRef) -> ErrorKind {
+    ErrorKind::NotInLoop(format!("${}{}", "^".repeat(r.up), r.name))
+}
+
+/// A table's values and which entry of its list it is, as [`Scope::within`] takes them
+pub(crate) type Level<'a> = (&'a HashMap<String, Value>, Option<(usize, usize)>);
+
+/// The values a line can see, which inside a `! for` are the entry's and then the outer ones
+pub(crate) struct Scope<'a> {
+    vals: &'a HashMap<String, Value>,
+    /// Which entry this is, counting from 0, and how many there are
+    entry: Option<(usize, usize)>,
+    /// How the entry is named in errors, e.g. `interface[2].`
+    path: String,
+    parent: Option<&'a Scope<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    pub(crate) fn new(vals: &'a HashMap<String, Value>) -> Self {
+        Self {
+            vals,
+            entry: None,
+            path: String::new(),
+            parent: None,
+        }
+    }
+
+    /**
+     * Runs `f` in the scope of the last of `levels`, inside the ones before it
+     *
+     * Each level is the values of a table and which entry of its list it is,
+     * outermost first, for code that walks the template without compiling it.
+     */
+    pub(crate) fn within<R>(levels: &[Level], f: impl FnOnce(&Scope) -> R) -> R {
+        fn nest<R>(parent: Option<&Scope>, levels: &[Level], f: impl FnOnce(&Scope) -> R) -> R {
+            let ((vals, entry), rest) = levels.split_first().expect("no levels");
+            let scope = Scope {
+                vals,
+                entry: *entry,
+                path: String::new(),
+                parent,
+            };
+            match rest {
+                [] => f(&scope),
+                _ => nest(Some(&scope), rest, f),
             }
         }
-        self
+        nest(None, levels, f)
     }
-    pub fn set_list_item(
-        mut self,
-        keys: &[(&str, usize)],
-        key: impl Into<String>,
-        value: impl Into<String>,
+
+    /// The scope of entry `n` of the list `name` in this one
+    fn entry(
+        &'a self,
+        vals: &'a HashMap<String, Value>,
+        name: &str,
+        n: usize,
+        count: usize,
     ) -> Self {
-        let mut cur = &mut self.vals;
-        for (k, num) in keys {
-            match cur.get_mut(&k.to_string()).expect("") {
-                Value::List(v) => {
-                    cur = &mut v[*num];
-                }
-                _ => panic!(""),
-            }
+        Self {
+            vals,
+            entry: Some((n, count)),
+            path: format!("{}{}[{}].", self.path, name, n),
+            parent: Some(self),
         }
-        Self::set_value(cur, key.into(), value.into());
-        self
     }
-    pub fn add_list_vec(mut self, key: impl Into<String>, vals: Vec<(&str, &str)>) -> Self {
-        match self.vals.get_mut(&key.into()).expect("Key doesn't exist") {
-            Value::List(v) => {
-                let mut new = v[0].clone();
-                for (k, v) in vals {
-                    Self::set_value(&mut new, k.to_owned(), v.to_owned());
-                }
-                v.push(new);
+
+    /// The scope a reference's `^`s point to
+    fn up(&self, r: &ParamRef) -> Result<&Self, ErrorKind> {
+        let mut ret = self;
+        for _ in 0..r.up {
+            ret = ret.parent.ok_or_else(|| not_in_loop(r))?;
+        }
+        Ok(ret)
+    }
+
+    fn get(&self, r: &ParamRef) -> Result<Cow<'a, Value>, ErrorKind> {
+        let scope = self.up(r)?;
+        if !r.name.starts_with('@') {
+            return scope
+                .vals
+                .get(&r.name)
+                .map(Cow::Borrowed)
+                .ok_or(ErrorKind::UnknownParam);
+        }
+        let (n, count) = scope.entry.ok_or_else(|| not_in_loop(r))?;
+        // `$@first` and `$@last` act like a `$name{yes}` flag
+        let flag = |b: bool| {
+            let s = if b { "yes" } else { "" };
+            Value::Selected(s.to_string(), vec!["yes".to_string()], r.optional)
+        };
+        Ok(Cow::Owned(match &r.name[..] {
+            // numbered from 1, as they go into the config
+            "@index" => Value::Simple((n + 1).to_string(), false),
+            "@count" => Value::Simple(count.to_string(), false),
+            "@first" => flag(n == 0),
+            "@last" => flag(n + 1 == count),
+            _ => return Err(ErrorKind::UnknownParam),
+        }))
+    }
+}
+
+impl Conf {
+    /// Checks a reference can be used inside `depth` `! for`s, and makes its value
+    pub(crate) fn declare_at(
+        r: &ParamRef,
+        n: usize,
+        depth: usize,
+    ) -> Result<Option<Value>, ConfError> {
+        let err = |kind| ConfError::new(kind).at(n, r.column).param(&r.name);
+        let loop_var = r.name.starts_with('@');
+        if r.up + loop_var as usize > depth {
+            return Err(err(not_in_loop(r)));
+        }
+        if loop_var {
+            if !LOOP_VARS.contains(&&r.name[..]) {
+                return Err(err(ErrorKind::BadName(format!("${}", r.name))));
             }
-            _ => panic!("Key isn't a list"),
+            // loop variables come from the `! for`, not the values
+            return Ok(None);
         }
-        self
+        declare(r, n).map(Some)
     }
-    fn compile_line(mut tmp: &str, vals: &HashMap<String, Value>) -> String {
-        let mut ret = String::default();
-        // `?\` is an optional command, ignored for now
-        let opt = if tmp.starts_with("?\\") {
-            tmp = &tmp[2..];
-            true
-        } else {
-            false
+    /// Stores a use in `ret`, merged with any earlier ones, or passes it out to its `! for`
+    fn store(
+        u: Use,
+        ret: &mut HashMap<String, Value>,
+        decl: &mut Declared,
+        outer: &mut Vec<Use>,
+    ) -> Result<(), ConfError> {
+        if u.r.up > 0 {
+            let r = ParamRef {
+                up: u.r.up - 1,
+                ..u.r
+            };
+            outer.push(Use { r, ..u });
+            return Ok(());
+        }
+        if u.weak {
+            ret.entry(u.r.name.clone()).or_insert(u.val);
+            return Ok(());
+        }
+        let Use {
+            r, val, line, file, ..
+        } = u;
+        let err = |kind| {
+            ConfError::new(kind)
+                .at(line, r.column)
+                .param(&r.name)
+                .in_file(file.as_deref())
         };
-        for w in tmp.split_whitespace().filter(|&w| w != "") {
-            if w.starts_with('"') {
-                ret += "\"";
+        let name = &r.name;
+        let at = location(line, file.as_deref());
+        let typed = decl
+            .check(name, ref_kind(&r), r.default.as_ref(), at)
+            .map_err(err)?;
+        // required if any use is, with the type of the use that decided it
+        let mut val = match ret.remove(name) {
+            Some(old) => {
+                let optional = old.is_optional() && val.is_optional();
+                let mut val = if typed { val } else { old };
+                val.set_optional(optional);
+                val
             }
-            let word = w.trim_matches('"');
-            if word.starts_with("$") || word.starts_with("?") {
-                let mut parts = word
-                    .split(|c| c == '$' || c == '?' || c == '{' || c == '}')
-                    .filter(|&s| s != "");
-                let mut name_parts = parts.next().unwrap().split("-").filter(|&s| s != "");
-                let a = vals.get(name_parts.next().unwrap());
-                if a.is_none() {
-                    panic!("Failed at {}, vals: {:?}", word, vals);
-                }
-                match a.unwrap() {
-                    Value::Simple(s, b) => {
-                        if s == "" && !b {
-                            if opt {
-                                return String::default();
-                            }
-                            panic!("Required value not supplied for {}", word);
-                        } else if s != "" {
-                            ret += &s;
-                            if w.ends_with('"') {
-                                ret += "\"";
-                            }
-                            ret += " ";
+            None => val,
+        };
+        // a default from another use still has to suit the type
+        if let (false, Some(d)) = (val.is_set(), &decl.params[name].default) {
+            val.set(d.clone()).map_err(err)?;
+        }
+        ret.insert(name.clone(), val);
+        Ok(())
+    }
+    /**
+     * Declares every parameter the nodes use
+     *
+     * Each parameter gets one type, and is required if any use of it is. A
+     * plain use takes the type of a typed one, wherever that is, but two
+     * typed uses that disagree are an error. `depth` is how
+     * many `! for`s the nodes are in, and `$^name` belongs to an outer
+     * one, so it's handed back through `outer`.
+     */
+    fn declare_nodes(
+        nodes: &[Node],
+        ret: &mut HashMap<String, Value>,
+        decl: &mut Declared,
+        depth: usize,
+        outer: &mut Vec<Use>,
+    ) -> Result<(), ConfError> {
+        for node in nodes {
+            match node {
+                Node::Line(l) => {
+                    for r in l.params() {
+                        let val = Self::declare_at(r, l.number, depth)
+                            .map_err(|e| e.in_file(l.file.as_deref()))?;
+                        if let Some(val) = val {
+                            let u = Use {
+                                r: r.clone(),
+                                val,
+                                weak: false,
+                                line: l.number,
+                                file: l.file.clone(),
+                            };
+                            Self::store(u, ret, decl, outer)?;
                         }
                     }
-                    Value::Selected(s, _l, b) => {
-                        if s == "" && !b {
-                            if opt {
-                                return String::default();
-                            }
-                            panic!("Required value not supplied for {}", word);
-                        } else if s != "" {
-                            ret += &s;
-                            if w.ends_with('"') {
-                                ret += "\"";
-                            }
-                            ret += " ";
-                        }
+                }
+                Node::For {
+                    line,
+                    file,
+                    name,
+                    body,
+                } => {
+                    let err = |kind| {
+                        ConfError::new(kind)
+                            .at(*line, 1)
+                            .param(name)
+                            .in_file(file.as_deref())
+                    };
+                    let at = location(*line, file.as_deref());
+                    let mut inner = decl.take_list(name, at).map_err(err)?;
+                    // a second `! for` of the same list adds to the first
+                    let mut hash = match ret.remove(name) {
+                        Some(Value::List(proto, _)) => proto,
+                        _ => HashMap::new(),
+                    };
+                    let mut up = Vec::new();
+                    Self::declare_nodes(body, &mut hash, &mut inner, depth + 1, &mut up)?;
+                    decl.put_list(name, inner);
+                    ret.insert(name.clone(), Value::List(hash, Vec::new()));
+                    for u in up {
+                        Self::store(u, ret, decl, outer)?;
                     }
-                    Value::Range(i, _r, b) => {
-                        if *i == -1 && !b {
-                            if opt {
-                                return String::default();
-                            }
-                            panic!("Required value not supplied for {}", word);
-                        } else {
-                            if *i != -1 {
-                                ret += &i.to_string();
-                                if w.ends_with('"') {
-                                    ret += "\"";
-                                }
-                                ret += " ";
-                            }
-                        }
+                }
+                Node::If {
+                    line,
+                    file,
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    let r = &cond.param;
+                    let val = Self::declare_at(r, *line, depth)
+                        .map_err(|e| e.in_file(file.as_deref()))?;
+                    if let Some(val) = val {
+                        // a condition on its own is just text, but a use elsewhere decides the type
+                        let weak = r.ty.is_none() && r.default.is_none();
+                        let u = Use {
+                            r: r.clone(),
+                            val: match weak {
+                                true => Value::Simple(String::default(), true),
+                                false => val,
+                            },
+                            weak,
+                            line: *line,
+                            file: file.clone(),
+                        };
+                        Self::store(u, ret, decl, outer)?;
                     }
-                    Value::IP(ip, b) => {
-                        if ip.ip_type() == IPType::None && !b {
-                            if opt {
-                                return String::default();
-                            }
-                            panic!("Required value not supplied for {}", word);
-                        } else if ip.ip_type() != IPType::None {
-                            if let Some(t) = name_parts.next() {
-                                if t == "mask" {
-                                    ret += &format!("{:#}", ip.subnet_mask());
-                                } else {
-                                    panic!("Malformed name {}", word);
-                                }
-                            } else {
-                                if let Some(type_name) = parts.next() {
-                                    if type_name == "\\ipslash" {
-                                        ret += &format!("{}", ip);
-                                    } else {
-                                        ret += &format!("{:#}", ip);
-                                    }
-                                } else {
-                                    ret += &format!("{:#}", ip);
-                                }
-                            }
-                            if w.ends_with('"') {
-                                ret += "\"";
-                            }
-                            ret += " ";
+                    Self::declare_nodes(then, ret, decl, depth, outer)?;
+                    Self::declare_nodes(otherwise, ret, decl, depth, outer)?;
+                }
+                Node::Section { body, .. } => Self::declare_nodes(body, ret, decl, depth, outer)?,
+                Node::Comment(..) => (),
+            }
+        }
+        Ok(())
+    }
+    /// Whether a condition holds, unset values only match `!=`
+    pub(crate) fn test(
+        cond: &Cond,
+        line: usize,
+        file: Option<&str>,
+        scope: &Scope,
+    ) -> Result<bool, ConfError> {
+        let r = &cond.param;
+        let err = |kind| {
+            ConfError::new(kind)
+                .at(line, r.column)
+                .param(&r.name)
+                .in_file(file)
+        };
+        let val = scope.get(r).map_err(err)?;
+        let value = match &*val {
+            Value::List(_, entries) => return Ok(cond.compare.is_none() && !entries.is_empty()),
+            v => render(r, v, scope).map_err(err)?,
+        };
+        Ok(match (&cond.compare, value) {
+            (None, v) => v.is_some(),
+            (Some((eq, want)), Some(v)) => (&v == want) == *eq,
+            (Some((eq, _)), None) => !eq,
+        })
+    }
+    pub fn parse(file: &str) -> Result<Self, ConfError> {
+        let nodes = template::load(file)?;
+        let mut h = HashMap::new();
+        Self::declare_nodes(&nodes, &mut h, &mut Declared::default(), 0, &mut Vec::new())
+            .map_err(|e| e.or_file(file))?;
+        Ok(Self {
+            vals: h,
+            nodes,
+            file: file.to_string(),
+        })
+    }
+    pub(crate) fn set_value(
+        hash: &mut HashMap<String, Value>,
+        key: String,
+        value: String,
+    ) -> Result<(), ConfError> {
+        let err = |kind| ConfError::new(kind).param(&key);
+        hash.get_mut(&key)
+            .ok_or_else(|| err(ErrorKind::UnknownParam))?
+            .set(value)
+            .map_err(err)
+    }
+    /// Gets a parameter as it would be written into the config, if it's been set
+    pub fn get(&self, key: &str) -> Option<String> {
+        get_in(&self.vals, key)
+    }
+    /// Gets a parameter of the list entry at `path`, e.g. `&[("acl", 0)]` for the first `acl`
+    pub fn get_list_item(&self, path: &[(&str, usize)], key: &str) -> Option<String> {
+        get_in(entry(&self.vals, path)?, key)
+    }
+    /// The number of entries in the list `key`, in the list entry at `path`
+    pub fn list_len(&self, path: &[(&str, usize)], key: &str) -> Option<usize> {
+        match entry(&self.vals, path)?.get(key)? {
+            Value::List(_, entries) => Some(entries.len()),
+            _ => None,
+        }
+    }
+    pub fn set(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Self, ConfError> {
+        Self::set_value(&mut self.vals, key.into(), value.into())
+            .map_err(|e| e.file(&self.file))?;
+        Ok(self)
+    }
+    pub fn set_present(mut self, key: impl Into<String>) -> Result<Self, ConfError> {
+        let key = key.into();
+        let Self { vals, file, .. } = &mut self;
+        let err = |kind| ConfError::new(kind).file(file.as_str()).param(&key);
+        match vals.get_mut(&key) {
+            Some(Value::Selected(s, l, _b)) if l.len() == 1 => *s = l[0].to_string(),
+            Some(_) => return Err(err(ErrorKind::NotPresent)),
+            None => return Err(err(ErrorKind::UnknownParam)),
+        }
+        Ok(self)
+    }
+    pub fn add_list(self, key: impl Into<String>) -> Result<Self, ConfError> {
+        self.add_list_count(key, 1)
+    }
+    pub fn add_list_count(mut self, key: impl Into<String>, num: usize) -> Result<Self, ConfError> {
+        let key = key.into();
+        for _ in 0..num {
+            self = self.add_list_item(&[], &key, Vec::new())?;
+        }
+        Ok(self)
+    }
+    pub fn add_list_vec(
+        self,
+        key: impl Into<String>,
+        vals: Vec<(&str, &str)>,
+    ) -> Result<Self, ConfError> {
+        self.add_list_item(&[], key, vals)
+    }
+    /**
+     * Appends an entry to the list `key`, filled in with `vals`
+     *
+     * `path` picks the list entry the list is in, as `(list, entry)` pairs
+     * counting from 0, so `&[("acl", 1)]` adds to a list in the second
+     * `acl`. An empty `path` is a top level list.
+     */
+    pub fn add_list_item(
+        mut self,
+        path: &[(&str, usize)],
+        key: impl Into<String>,
+        vals: Vec<(&str, &str)>,
+    ) -> Result<Self, ConfError> {
+        let key = key.into();
+        let Self {
+            vals: hash, file, ..
+        } = &mut self;
+        let mut name = String::new();
+        let cur = entry_mut(hash, path, &mut name).map_err(|e| e.file(file.as_str()))?;
+        let (proto, entries) = list_mut(cur, &key)
+            .map_err(|e| e.file(file.as_str()).param(format!("{}{}", name, key)))?;
+        let mut new = proto.clone();
+        for (k, val) in vals {
+            Self::set_value(&mut new, k.to_owned(), val.to_owned()).map_err(|e| {
+                e.file(file.as_str())
+                    .param(format!("{}{}[{}].{}", name, key, entries.len(), k))
+            })?;
+        }
+        entries.push(new);
+        Ok(self)
+    }
+    /// Sets a parameter of the list entry at `path`, which counts from 0 like `add_list_item`
+    pub fn set_list_item(
+        mut self,
+        path: &[(&str, usize)],
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Self, ConfError> {
+        let key = key.into();
+        let Self { vals, file, .. } = &mut self;
+        let mut name = String::new();
+        let cur = entry_mut(vals, path, &mut name).map_err(|e| e.file(file.as_str()))?;
+        Self::set_value(cur, key.clone(), value.into())
+            .map_err(|e| e.file(file.as_str()).param(name + &key))?;
+        Ok(self)
+    }
+    /**
+     * Fills in a line, or `None` if it's an optional command missing something
+     *
+     * A `?\` line is left out when any parameter it uses isn't set, even a
+     * `?name` one. Elsewhere an unset `?name` is left out along with its space.
+     */
+    fn compile_line(l: &Line, scope: &Scope) -> Result<Option<String>, ConfError> {
+        // parameters only filters use, e.g. the `mac` in `|eui64:mac`, are required too
+        for a in &l.args {
+            let err = |kind| l.error(kind, a.column).param(&a.name);
+            let val = scope.get(a).map_err(err)?;
+            if !val.is_set() && !val.is_optional() && !l.optional {
+                return Err(err(ErrorKind::Missing));
+            }
+        }
+        let mut ret = l.indent.clone();
+        // after an empty parameter, the space that separated it goes too
+        let mut eat_space = false;
+        for part in &l.parts {
+            match part {
+                Part::Text(t) if eat_space => ret += t.trim_start(),
+                Part::Text(t) => ret += t,
+                Part::Param(r) => {
+                    let err = |kind| l.error(kind, r.column).param(&r.name);
+                    let val = scope.get(r).map_err(err)?;
+                    match render(r, &val, scope).map_err(err)? {
+                        Some(s) => ret += &s,
+                        None if l.optional => return Ok(None),
+                        None if !val.is_optional() => return Err(err(ErrorKind::Missing)),
+                        None => {
+                            let keep = ret.trim_end().len().max(l.indent.len());
+                            ret.truncate(keep);
+                            eat_space = ret.len() == l.indent.len();
+                            continue;
                         }
                     }
-                    Value::Interface(it, b) => {
-                        if it.is_none() && !b {
-                            if opt {
-                                return String::default();
-                            }
-                            panic!("Required value not supplied for {}", word);
-                        } else if !it.is_none() {
-                            ret += &it.fmt();
-                            if w.ends_with('"') {
-                                ret += "\"";
+                }
+            }
+            eat_space = false;
+        }
+        Ok(Some(ret.trim_end().to_string()))
+    }
+    fn compile_nodes(
+        nodes: &[Node],
+        scope: &Scope,
+        ret: &mut Vec<String>,
+    ) -> Result<(), ConfError> {
+        for node in nodes {
+            match node {
+                Node::Line(l) => ret.extend(Self::compile_line(l, scope)?),
+                Node::For {
+                    line,
+                    file,
+                    name,
+                    body,
+                } => {
+                    let err = |kind| {
+                        ConfError::new(kind)
+                            .at(*line, 1)
+                            .param(name)
+                            .in_file(file.as_deref())
+                    };
+                    match scope
+                        .vals
+                        .get(name)
+                        .ok_or_else(|| err(ErrorKind::UnknownParam))?
+                    {
+                        Value::List(_, entries) => {
+                            for (n, map) in entries.iter().enumerate() {
+                                let scope = scope.entry(map, name, n, entries.len());
+                                Self::compile_nodes(body, &scope, ret)?;
                             }
-                            ret += " ";
                         }
+                        _ => return Err(err(ErrorKind::NotAList)),
                     }
-                    Value::List(_l) => unreachable!(),
                 }
-            } else if word.starts_with("\\$") {
-                ret += &word[1..];
-                if w.ends_with('"') {
-                    ret += "\"";
+                Node::If {
+                    line,
+                    file,
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    let body = if Self::test(cond, *line, file.as_deref(), scope)? {
+                        then
+                    } else {
+                        otherwise
+                    };
+                    Self::compile_nodes(body, scope, ret)?;
+                }
+                Node::Section { body, .. } => Self::compile_nodes(body, scope, ret)?,
+                Node::Comment(..) => (),
+            }
+        }
+        Ok(())
+    }
+    fn validate_line(l: &Line, scope: &Scope, ret: &mut Vec<ConfError>) {
+        for r in l.params() {
+            // `$^name` is reported by the name it has in its own scope
+            let path = scope.up(r).map(|s| &s.path[..]).unwrap_or_default();
+            let full = format!("{}{}", path, r.name);
+            let err = |kind| l.error(kind, r.column).param(&full);
+            // the setters already check these, but report anything that slipped past them
+            let val = scope.get(r);
+            let kind = match val.as_deref() {
+                Err(e) => Some(e.clone()),
+                Ok(Value::Range(i, r, _)) if *i != -1 && !r.contains(i) => {
+                    Some(ErrorKind::OutOfRange(*i, r.clone()))
+                }
+                Ok(Value::Selected(s, l, _)) if !s.is_empty() && !l.contains(s) => {
+                    Some(ErrorKind::InvalidChoice(s.clone(), l.clone()))
                 }
-                ret += " ";
-            } else {
-                ret += word;
-                if w.ends_with('"') {
-                    ret += "\"";
+                // `?\` lines are dropped rather than failing, so they never miss anything
+                Ok(v) if !v.is_set() && !r.optional && !l.optional => Some(ErrorKind::Missing),
+                Ok(_) => None,
+            };
+            if let Some(kind) = kind {
+                // each parameter is only reported once, at the first line it's used
+                if !ret
+                    .iter()
+                    .any(|e| e.param.as_ref() == Some(&full) && e.kind == kind)
+                {
+                    ret.push(err(kind));
                 }
-                ret += " ";
             }
         }
-        ret.trim().to_owned()
     }
-    fn compile_pattern(
-        v: &Vec<String>,
-        i: &mut usize,
-        vals: Option<&HashMap<String, Value>>,
-    ) -> Vec<String> {
-        // let mut i = v.iter();
-        let mut ret = Vec::new();
-        while *i < v.len() {
-            let tmp: &str = v[*i].trim();
-            // `!` is ignored, excpet when parsing for loops
-            if tmp.starts_with("!") || tmp.starts_with("#") {
-                if tmp.contains("! for $") || tmp.contains("# for $") {
-                    let name = tmp[7..].split_whitespace().next().expect("").to_string();
-                    if let Some(val) = vals {
-                        match val.get(&name).expect(&name) {
-                            Value::List(list) => {
-                                *i += 1;
-                                for map in list.iter().skip(1) {
-                                    let mut start = *i;
-                                    ret.append(&mut Self::compile_pattern(
-                                        &v,
-                                        &mut start,
-                                        Some(map),
-                                    ));
-                                }
-                                Self::compile_pattern(&v, i, None);
-                            }
-                            _ => panic!("bad"),
+    fn validate_nodes(nodes: &[Node], scope: &Scope, ret: &mut Vec<ConfError>) {
+        // mirrors compile_nodes, but records problems instead of compiling
+        for node in nodes {
+            match node {
+                Node::Line(l) => Self::validate_line(l, scope, ret),
+                Node::For { name, body, .. } => {
+                    if let Some(Value::List(_, entries)) = scope.vals.get(name) {
+                        for (n, map) in entries.iter().enumerate() {
+                            let scope = scope.entry(map, name, n, entries.len());
+                            Self::validate_nodes(body, &scope, ret);
                         }
-                    } else {
-                        Self::compile_pattern(&v, i, None);
                     }
-                } else if tmp.contains("! end for")
-                    || tmp.contains("! end conf")
-                    || tmp.contains("# end for")
-                    || tmp.contains("# end conf")
-                {
-                    *i += 1;
-                    break;
-                } else {
-                    *i += 1;
                 }
-            } else {
-                if let Some(val) = vals {
-                    ret.push(Self::compile_line(tmp, &val));
-                }
-                *i += 1;
+                // only the branch that will be compiled needs its values
+                Node::If {
+                    line,
+                    file,
+                    cond,
+                    then,
+                    otherwise,
+                } => match Self::test(cond, *line, file.as_deref(), scope) {
+                    Ok(true) => Self::validate_nodes(then, scope, ret),
+                    Ok(false) => Self::validate_nodes(otherwise, scope, ret),
+                    Err(e) => ret.push(ConfError {
+                        param: e.param.map(|p| format!("{}{}", scope.path, p)),
+                        ..e
+                    }),
+                },
+                Node::Section { body, .. } => Self::validate_nodes(body, scope, ret),
+                Node::Comment(..) => (),
             }
         }
+    }
+    /**
+     * Checks the whole template against the current values
+     *
+     * Walks every `! for` entry, and reports every missing required value,
+     * out of range number and invalid choice, with the line it came from.
+     * List entries are named `list[n].key`, counting from 0. Only lines
+     * from an included template have a file set.
+     */
+    pub fn validate(&self) -> Vec<ConfError> {
+        let mut ret = Vec::new();
+        Self::validate_nodes(&self.nodes, &Scope::new(&self.vals), &mut ret);
         ret
     }
-    pub fn compile(self) -> Vec<String> {
-        Self::compile_pattern(&self.conf, &mut 0, Some(&self.vals))
+    /// Lists every required parameter that doesn't have a value yet
+    pub fn missing(&self) -> Vec<String> {
+        self.validate()
+            .into_iter()
+            .filter(|e| e.kind == ErrorKind::Missing)
+            .filter_map(|e| e.param)
+            .collect()
+    }
+    pub fn compile(self) -> Result<Vec<String>, ConfError> {
+        let mut ret = Vec::new();
+        Self::compile_nodes(&self.nodes, &Scope::new(&self.vals), &mut ret)
+            .map_err(|e| e.or_file(&self.file))?;
+        Ok(ret)
     }
     #[allow(unused)]
     pub fn debug(self) -> Self {
@@ -404,3 +1096,566 @@ impl std::fmt::Debug for Conf {
         write!(f, "Conf {:?}", self.vals)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conf() -> Conf {
+        Conf::parse("tests/data/values.conf").unwrap()
+    }
+
+    fn kind<T>(res: Result<T, ConfError>) -> ErrorKind {
+        match res {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.kind,
+        }
+    }
+
+    #[test]
+    fn template_errors() {
+        let e = Conf::parse("tests/data/bad-type.conf").err().unwrap();
+        assert_eq!(e.kind, ErrorKind::UnknownType("\\nope".to_string()));
+        assert_eq!(e.file.as_deref(), Some("tests/data/bad-type.conf"));
+        assert_eq!((e.line, e.column), (Some(2), Some(11)));
+        assert_eq!(e.param.as_deref(), Some("name"));
+        let e = Conf::parse("tests/data/bad-range.conf").err().unwrap();
+        assert_eq!(e.kind, ErrorKind::BadRange("1000..".to_string()));
+        assert_eq!((e.line, e.column), (Some(2), Some(16)));
+        let e = Conf::parse("tests/data/none.conf").err().unwrap();
+        assert!(matches!(e.kind, ErrorKind::Io(_)));
+        let e = Conf::parse("tests/data/latin1.conf").err().unwrap();
+        assert!(matches!(&e.kind, ErrorKind::Io(m) if m.starts_with("isn't valid UTF-8")));
+        assert_eq!(e.line, Some(3));
+    }
+
+    #[test]
+    fn set_errors() {
+        assert_eq!(kind(conf().set("bogus", "x")), ErrorKind::UnknownParam);
+        assert_eq!(kind(conf().set("interface", "x")), ErrorKind::IsList);
+        assert_eq!(kind(conf().set_present("hostname")), ErrorKind::NotPresent);
+        assert_eq!(kind(conf().add_list("hostname")), ErrorKind::NotAList);
+        assert_eq!(kind(conf().add_list("bogus")), ErrorKind::UnknownParam);
+        let e = conf()
+            .set("hostname", "R1")
+            .unwrap()
+            .set("nodns", "yes")
+            .err()
+            .unwrap();
+        assert_eq!(
+            e.kind,
+            ErrorKind::InvalidChoice("yes".to_string(), vec!["no".to_string()])
+        );
+        assert_eq!(e.param.as_deref(), Some("nodns"));
+    }
+
+    #[test]
+    fn list_value_errors() {
+        let conf = conf().add_list("interface").unwrap();
+        let at = |n| [("interface", n)];
+        assert_eq!(
+            kind(conf.clone().set_list_item(&at(0), "ip", "1.2.3")),
+            ErrorKind::BadIP(IPError::MissingPart)
+        );
+        assert!(matches!(
+            kind(conf.clone().set_list_item(&at(0), "iface", "x1")),
+            ErrorKind::BadInterface(_)
+        ));
+        assert_eq!(
+            kind(conf.clone().set_list_item(&at(5), "ip", "1.2.3.4/8")),
+            ErrorKind::NoEntry(5)
+        );
+        assert_eq!(
+            kind(conf.add_list_vec("interface", vec![("nat.side", "up")])),
+            ErrorKind::InvalidChoice(
+                "up".to_string(),
+                vec!["inside".to_string(), "outside".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn compile_errors() {
+        let e = conf().compile().err().unwrap();
+        assert_eq!(e.kind, ErrorKind::Missing);
+        assert_eq!(e.param.as_deref(), Some("hostname"));
+        assert_eq!(e.line, Some(2));
+    }
+
+    #[test]
+    fn validate_reports_everything() {
+        let mut conf = conf()
+            .add_list_vec("interface", vec![("iface", "g0/0")])
+            .unwrap()
+            .add_list_vec("interface", vec![("iface", "g0/1"), ("ip", "10.0.0.1/8")])
+            .unwrap()
+            .add_list("interface")
+            .unwrap();
+        // the setters never let these through, so put them in by hand
+        conf.vals.insert(
+            "nodns".to_string(),
+            Value::Selected("yes".to_string(), vec!["no".to_string()], false),
+        );
+        let errs: Vec<_> = conf
+            .validate()
+            .into_iter()
+            .map(|e| (e.param.unwrap(), e.kind, e.line.unwrap()))
+            .collect();
+        assert_eq!(
+            errs,
+            [
+                ("hostname".to_string(), ErrorKind::Missing, 2),
+                (
+                    "nodns".to_string(),
+                    ErrorKind::InvalidChoice("yes".to_string(), vec!["no".to_string()]),
+                    3
+                ),
+                ("interface[0].ip".to_string(), ErrorKind::Missing, 6),
+                ("interface[2].iface".to_string(), ErrorKind::Missing, 5),
+                ("interface[2].ip".to_string(), ErrorKind::Missing, 6),
+            ]
+        );
+        assert_eq!(
+            conf.missing(),
+            [
+                "hostname",
+                "interface[0].ip",
+                "interface[2].iface",
+                "interface[2].ip"
+            ]
+        );
+    }
+
+    #[test]
+    fn if_branches() {
+        let conf = Conf::parse("tests/data/if.conf")
+            .unwrap()
+            .set("hostname", "R1")
+            .unwrap()
+            .set("iface", "g0/1")
+            .unwrap();
+        let access = conf
+            .clone()
+            .set("host", "ntp")
+            .unwrap()
+            .set("hostip", "10.0.0.5")
+            .unwrap()
+            .set("vlan", "10")
+            .unwrap();
+        assert_eq!(
+            access.clone().compile().unwrap(),
+            [
+                "hostname R1",
+                "no ip domain-lookup",
+                "ip host ntp 10.0.0.5",
+                "interface GigabitEthernet 0/1",
+                "    switchport mode access",
+                "    switchport access vlan 10",
+                "    switchport trunk native vlan",
+            ]
+        );
+        let lines = access.set("mode", "access").unwrap().compile().unwrap();
+        assert_eq!(lines.last().unwrap(), "    switchport access vlan 10");
+
+        let trunk = conf
+            .set("domain", "lab")
+            .unwrap()
+            .set("dns", "10.0.0.2/32")
+            .unwrap()
+            .set("mode", "trunk")
+            .unwrap()
+            .add_list_vec("helpers", vec![("address", "10.0.0.9")])
+            .unwrap();
+        assert_eq!(
+            trunk.compile().unwrap(),
+            [
+                "hostname R1",
+                "ip domain-name lab",
+                "ip name-server 10.0.0.2",
+                "interface GigabitEthernet 0/1",
+                "    switchport mode trunk",
+                "    switchport trunk native vlan",
+                "    ip helper-address 10.0.0.9",
+            ]
+        );
+    }
+
+    #[test]
+    fn if_needs_its_side() {
+        // the `! else` side is compiled until `$domain` is set
+        let conf = Conf::parse("tests/data/if.conf")
+            .unwrap()
+            .set("hostname", "R1")
+            .unwrap();
+        let e = conf.clone().compile().unwrap_err();
+        assert_eq!(
+            (e.kind, e.param.as_deref()),
+            (ErrorKind::Missing, Some("host"))
+        );
+        let e = conf.set("domain", "lab").unwrap().compile().unwrap_err();
+        assert_eq!(
+            (e.kind, e.param.as_deref()),
+            (ErrorKind::Missing, Some("dns"))
+        );
+    }
+
+    fn filters(ip: &str, net: &str) -> Result<Vec<String>, ConfError> {
+        Conf::parse("tests/data/filters.conf")
+            .unwrap()
+            .set("ip", ip)?
+            .set("net", net)?
+            .set("name", "Edge-1")?
+            .compile()
+    }
+
+    #[test]
+    fn each_filter() {
+        assert_eq!(
+            filters("10.1.2.3/24", "192.168.0.9/30").unwrap(),
+            [
+                "mask 255.255.255.0",
+                "wildcard 0.0.0.255",
+                "network 10.1.2.0",
+                "first 10.1.2.1",
+                "last 10.1.2.254",
+                "broadcast 10.1.2.255",
+                "upper EDGE-1",
+                "lower edge-1",
+                "chained 10.1.2.0",
+                "slash 192.168.0.8/30",
+            ]
+        );
+        let lines = filters("10.0.0.1/32", "10.0.0.1/31").unwrap();
+        assert_eq!(
+            lines[3..6],
+            ["first 10.0.0.1", "last 10.0.0.1", "broadcast 10.0.0.1"]
+        );
+        assert_eq!(lines[9], "slash 10.0.0.0/31");
+    }
+
+    #[test]
+    fn address_filters_need_an_address() {
+        let e = filters("dhcp", "10.0.0.1/31").unwrap_err();
+        assert_eq!(e.kind, ErrorKind::NoAddress("wildcard".to_string()));
+        assert_eq!((e.line, e.column), (Some(3), Some(10)));
+    }
+
+    #[test]
+    fn ipv6_filters() {
+        let conf = Conf::parse("tests/data/filters6.conf").unwrap();
+        // `mac` is a parameter of its own, and `|eui64:mac` took its type
+        assert_eq!(conf.missing().len(), 2);
+        let lines = conf
+            .clone()
+            .set("lan", "2001:db8:1::/64")
+            .unwrap()
+            .set("mac", "0011.2233.4455")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(
+            lines,
+            [
+                "eui64 2001:db8:1:0:211:22ff:fe33:4455/64",
+                "network 2001:db8:1::/64",
+                "link-local fe80::211:22ff:fe33:4455",
+                "upper FE80::211:22FF:FE33:4455",
+            ]
+        );
+        let e = conf
+            .set("lan", "2001:db8:1::/64")
+            .unwrap()
+            .compile()
+            .unwrap_err();
+        assert_eq!(e.kind, ErrorKind::Missing);
+        assert_eq!(e.param.as_deref(), Some("mac"));
+    }
+
+    #[test]
+    fn ipany_filters() {
+        let compile = |ip: &str| {
+            Conf::parse("tests/data/ipany.conf")
+                .unwrap()
+                .set("ip", ip)?
+                .compile()
+        };
+        assert_eq!(
+            compile("10.1.2.3/24").unwrap(),
+            ["family ipv4", "ip route 10.1.2.0 255.255.255.0"]
+        );
+        assert_eq!(
+            compile("2001:db8:1::1/48").unwrap(),
+            ["family ipv6", "ipv6 route 2001:db8:1::/48"]
+        );
+        // `dhcp` has a family, but no network
+        let e = compile("dhcp").unwrap_err();
+        assert_eq!(e.kind, ErrorKind::NoAddress("network".to_string()));
+        assert_eq!(e.line, Some(6));
+        assert_eq!(
+            compile("2001:db8::g/64").unwrap_err().kind,
+            ErrorKind::BadIP(IPError::BadGroup("g".to_string()))
+        );
+    }
+
+    #[test]
+    fn filter_errors() {
+        let declare = |text: &str| {
+            let line = template::lex_line(text, 1).unwrap();
+            let r = line.params().next().unwrap().clone();
+            declare(&r, 1)
+        };
+        let err = |text: &str| kind(declare(text));
+        assert_eq!(
+            err("$ip{\\ip}|bogus"),
+            ErrorKind::UnknownFilter("bogus".to_string())
+        );
+        assert_eq!(
+            err("$name|network"),
+            ErrorKind::NotAnIP("network".to_string())
+        );
+        assert_eq!(
+            err("$n{1..5}|first"),
+            ErrorKind::NotAnIP("first".to_string())
+        );
+        assert_eq!(
+            err("$ip{\\ip}|upper|network"),
+            ErrorKind::FilterAfterText("network".to_string(), "upper".to_string())
+        );
+        assert_eq!(
+            err("$ip{\\ip}|eui64:mac"),
+            ErrorKind::FilterType("eui64".to_string(), "\\ipv6".to_string())
+        );
+        assert_eq!(
+            err("$ip{\\ipv6}|eui64"),
+            ErrorKind::FilterParam("eui64".to_string())
+        );
+        assert_eq!(
+            err("$ip{\\ipv6}|network:mac"),
+            ErrorKind::UnknownFilter("network:mac".to_string())
+        );
+        assert_eq!(
+            err("$ip{\\ipv6}|link_local"),
+            ErrorKind::FilterType("link_local".to_string(), "\\mac".to_string())
+        );
+        assert_eq!(
+            err("$mac{\\mac}|link_local|network"),
+            ErrorKind::NotAnIP("network".to_string())
+        );
+        assert_eq!(
+            err("$ip{\\ipany}|family|network"),
+            ErrorKind::FilterAfterText("network".to_string(), "family".to_string())
+        );
+        assert_eq!(
+            err("$ip{\\ipany}|eui64:mac"),
+            ErrorKind::FilterType("eui64".to_string(), "\\ipv6".to_string())
+        );
+        assert!(declare("$ip{\\ipany}|network|family|upper").is_ok());
+        assert!(declare("$ip{\\ip}|network|upper|lower").is_ok());
+        assert!(declare("$ip{\\ipv6}|eui64:mac|first|upper").is_ok());
+    }
+
+    #[test]
+    fn defaults() {
+        let conf = Conf::parse("tests/data/defaults.conf").unwrap();
+        assert!(conf.missing().is_empty());
+        assert_eq!(
+            conf.clone().compile().unwrap(),
+            ["hostname router", "ip ssh time-out 60", "ip ssh version 2"]
+        );
+        let lines = conf.set("timeout", "30").unwrap().compile().unwrap();
+        assert_eq!(lines[1], "ip ssh time-out 30");
+    }
+
+    #[test]
+    fn bad_defaults() {
+        let e = Conf::parse("tests/data/bad-default.conf").unwrap_err();
+        assert_eq!(e.kind, ErrorKind::OutOfRange(500, 1..121));
+        assert_eq!((e.line, e.column), (Some(2), Some(17)));
+        assert_eq!(e.param.as_deref(), Some("timeout"));
+    }
+
+    #[test]
+    fn loops() {
+        let conf = Conf::parse("tests/data/loops.conf")
+            .unwrap()
+            .set("hostname", "R1")
+            .unwrap()
+            .set("limit", "50")
+            .unwrap()
+            .add_list_vec("vlan", vec![("id", "10"), ("name", "users")])
+            .unwrap()
+            .add_list_vec("vlan", vec![("id", "20"), ("name", "voice")])
+            .unwrap()
+            .add_list_vec("peer", vec![("ip", "10.0.0.1/32")])
+            .unwrap();
+        assert_eq!(
+            conf.clone().compile().unwrap(),
+            [
+                "hostname R1",
+                "max 50",
+                "vlan 10",
+                " name users 1/2",
+                " description first on R1",
+                "vlan 20",
+                " name voice 2/2",
+                " description last",
+                "neighbor 10.0.0.1 limit 50",
+            ]
+        );
+        // `$^limit` is the typed `limit` outside, not a new plain param
+        assert_eq!(
+            kind(conf.set("limit", "500")),
+            ErrorKind::OutOfRange(500, 0..100)
+        );
+    }
+
+    #[test]
+    fn not_in_loop() {
+        let e = Conf::parse("tests/data/not-in-loop.conf").unwrap_err();
+        assert_eq!(e.kind, ErrorKind::NotInLoop("$^host".to_string()));
+        assert_eq!((e.line, e.column), (Some(3), Some(9)));
+    }
+
+    #[test]
+    fn nested_lists() {
+        let conf = Conf::parse("tests/data/loops.conf")
+            .unwrap()
+            .set("hostname", "R1")
+            .unwrap()
+            .set("limit", "50")
+            .unwrap()
+            .add_list_vec("vlan", vec![("id", "10"), ("name", "users")])
+            .unwrap()
+            .add_list_vec("vlan", vec![("id", "20"), ("name", "voice")])
+            .unwrap()
+            .add_list_item(&[("vlan", 1)], "port", vec![("iface", "g0/1")])
+            .unwrap()
+            .add_list_item(&[("vlan", 1)], "port", vec![])
+            .unwrap()
+            .set_list_item(&[("vlan", 1), ("port", 1)], "iface", "g0/2")
+            .unwrap();
+        assert_eq!(conf.list_len(&[], "vlan"), Some(2));
+        assert_eq!(conf.list_len(&[("vlan", 0)], "port"), Some(0));
+        assert_eq!(conf.list_len(&[("vlan", 1)], "port"), Some(2));
+        assert_eq!(conf.list_len(&[("vlan", 2)], "port"), None);
+        assert_eq!(conf.list_len(&[], "hostname"), None);
+        assert_eq!(
+            conf.get_list_item(&[("vlan", 0)], "id").as_deref(),
+            Some("10")
+        );
+        let port = [("vlan", 1), ("port", 1)];
+        assert_eq!(
+            conf.get_list_item(&port, "iface").as_deref(),
+            Some("GigabitEthernet 0/2")
+        );
+        assert_eq!(
+            conf.get_list_item(&[("vlan", 1), ("port", 2)], "iface"),
+            None
+        );
+        assert_eq!(
+            conf.clone().compile().unwrap()[5..],
+            [
+                "vlan 20",
+                " name voice 2/2",
+                " description last",
+                " interface GigabitEthernet 0/1 on R1 vlan 20",
+                " interface GigabitEthernet 0/2 on R1 vlan 20",
+            ]
+        );
+
+        // errors name the entry they're in
+        let e = conf
+            .clone()
+            .set_list_item(&[("vlan", 1), ("port", 0)], "iface", "x1")
+            .unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::BadInterface(_)));
+        assert_eq!(e.param.as_deref(), Some("vlan[1].port[0].iface"));
+        let e = conf
+            .clone()
+            .add_list_item(&[("vlan", 1)], "port", vec![("iface", "x1")])
+            .unwrap_err();
+        assert_eq!(e.param.as_deref(), Some("vlan[1].port[2].iface"));
+        let e = conf
+            .clone()
+            .add_list_item(&[("vlan", 2)], "port", vec![])
+            .unwrap_err();
+        assert_eq!(e.kind, ErrorKind::NoEntry(2));
+        assert_eq!(e.param.as_deref(), Some("vlan"));
+        let e = conf
+            .add_list_item(&[("vlan", 0)], "bogus", vec![])
+            .unwrap_err();
+        assert_eq!(e.kind, ErrorKind::UnknownParam);
+        assert_eq!(e.param.as_deref(), Some("vlan[0].bogus"));
+    }
+
+    #[test]
+    fn merged_declarations() {
+        let conf = Conf::parse("tests/data/declare/merged.conf").unwrap();
+        // one use of `site` is required, so it is
+        assert_eq!(conf.missing(), ["site", "gw"]);
+        // a plain `$gw` takes the type of `$gw{\ip}`, and `$timeout` the default
+        assert!(matches!(conf.vals["gw"], Value::IP(..)));
+        assert!(matches!(conf.vals["timeout"], Value::Range(60, _, false)));
+        assert!(matches!(conf.vals["retries"], Value::Range(3, _, false)));
+        assert!(matches!(
+            kind(conf.clone().set("gw", "10.0.0")),
+            ErrorKind::BadIP(_)
+        ));
+        assert_eq!(
+            kind(conf.clone().set("timeout", "600")),
+            ErrorKind::OutOfRange(600, 1..120)
+        );
+        let lines = conf
+            .set("site", "hq")
+            .unwrap()
+            .set("gw", "10.0.0.1/24")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(
+            lines[..5],
+            [
+                "snmp-server location hq",
+                "hostname hq",
+                "ip route 0.0.0.0 0.0.0.0 10.0.0.1",
+                "ip default-gateway 10.0.0.1",
+                "ip address 10.0.0.1/24",
+            ]
+        );
+    }
+
+    #[test]
+    fn conflicting_declarations() {
+        let conflict = |file: &str| {
+            let e = Conf::parse(&format!("tests/data/declare/{}.conf", file)).unwrap_err();
+            (e.line, e.kind)
+        };
+        let kind = |here: &str, there: &str, at: &str| {
+            ErrorKind::ConflictingType(here.to_string(), there.to_string(), at.to_string())
+        };
+        assert_eq!(
+            conflict("typed"),
+            (Some(3), kind("`{\\interface}`", "`{1..4094}`", "line 2"))
+        );
+        assert_eq!(
+            conflict("list"),
+            (Some(3), kind("a `! for` list", "plain text", "line 2"))
+        );
+        assert_eq!(
+            conflict("defaults"),
+            (
+                Some(3),
+                kind(
+                    "`{1..120}` with default `90`",
+                    "plain text with default `60`",
+                    "line 2"
+                )
+            )
+        );
+        // a plain default still has to fit the type given elsewhere
+        assert_eq!(
+            conflict("range"),
+            (Some(3), ErrorKind::OutOfRange(600, 1..120))
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::session::{Credentials, Session, SessionError};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::time::Duration;
+
+// telnet commands, from RFC 854
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+// options we let the device turn on, the rest are refused
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+
+/**
+ * Splits `host[:port]` into the host and port, using `port` if there isn't one
+ *
+ * An IPv6 address needs brackets to be given a port, e.g. `[2001:db8::1]:2323`,
+ * otherwise the whole of it is the host.
+ */
+fn host_port<'a>(host: &'a str, port: &'a str) -> (&'a str, &'a str) {
+    if let Some(rest) = host.strip_prefix('[') {
+        if let Some((addr, after)) = rest.split_once(']') {
+            return match after.strip_prefix(':') {
+                Some(p) => (addr, p),
+                None => (addr, port),
+            };
+        }
+    }
+    match host.split_once(':') {
+        Some((h, p)) if !p.contains(':') => (h, p),
+        _ => (host, port),
+    }
+}
+
+/// What an `IAC` at the start of a buffer turned out to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sequence {
+    /// An escaped 255 in the data, two bytes long
+    Escaped,
+    /// A command or negotiation, already answered, this many bytes long
+    Command(usize),
+    /// The rest of it hasn't been read yet
+    Incomplete,
+}
+
+/**
+ * A telnet connection, with option negotiation stripped out
+ *
+ * The device may echo and suppress go-ahead, everything else is refused.
+ */
+pub struct Telnet {
+    stream: TcpStream,
+    // bytes of a command split across reads, or that didn't fit the last read
+    partial: Vec<u8>,
+}
+
+impl Telnet {
+    /// Connects to `host`, on port 23 unless it has one, e.g. `10.0.0.1:2323` or `[::1]:2323`
+    pub fn connect(host: &str) -> io::Result<Self> {
+        let (host, port) = host_port(host, "23");
+        let port: u16 = port.parse().map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("`{}` isn't a port number", port),
+            )
+        })?;
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+        Ok(Self {
+            stream,
+            partial: Vec::new(),
+        })
+    }
+
+    /// Answers a negotiation at the start of `buf`
+    fn negotiate(&mut self, buf: &[u8]) -> io::Result<Sequence> {
+        match buf.get(1) {
+            None => Ok(Sequence::Incomplete),
+            Some(&IAC) => Ok(Sequence::Escaped),
+            Some(&cmd @ (DO | DONT | WILL | WONT)) => {
+                let opt = match buf.get(2) {
+                    Some(o) => *o,
+                    None => return Ok(Sequence::Incomplete),
+                };
+                let answer = match cmd {
+                    WILL if opt == ECHO || opt == SUPPRESS_GO_AHEAD => DO,
+                    WILL | WONT => DONT,
+                    _ => WONT,
+                };
+                self.stream.write_all(&[IAC, answer, opt])?;
+                Ok(Sequence::Command(3))
+            }
+            // subnegotiation runs to `IAC SE`, with any 255 in it escaped
+            Some(&SB) => {
+                let mut i = 2;
+                while i + 1 < buf.len() {
+                    match (buf[i], buf[i + 1]) {
+                        (IAC, SE) => return Ok(Sequence::Command(i + 2)),
+                        (IAC, _) => i += 2,
+                        _ => i += 1,
+                    }
+                }
+                Ok(Sequence::Incomplete)
+            }
+            Some(_) => Ok(Sequence::Command(2)),
+        }
+    }
+}
+
+impl Read for Telnet {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut bytes = [0u8; 256];
+        let mut closed = false;
+        let n = match self.stream.read(&mut bytes) {
+            Ok(0) => {
+                // anything left from before still gets handed out
+                closed = true;
+                0
+            }
+            Ok(n) => n,
+            // a timeout keeps what's left of the last read, which may still be enough
+            Err(e)
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                    && !self.partial.is_empty() =>
+            {
+                0
+            }
+            Err(e) => return Err(e),
+        };
+        let mut buf = std::mem::take(&mut self.partial);
+        buf.extend_from_slice(&bytes[..n]);
+        let mut ret = 0;
+        let mut i = 0;
+        while i < buf.len() && ret < out.len() {
+            if buf[i] == IAC {
+                let seq = match self.negotiate(&buf[i..]) {
+                    Ok(seq) => seq,
+                    Err(e) => {
+                        // hand out what's been read, and answer it next time
+                        self.partial = buf.split_off(i);
+                        return if ret > 0 { Ok(ret) } else { Err(e) };
+                    }
+                };
+                match seq {
+                    Sequence::Escaped => {
+                        out[ret] = IAC;
+                        ret += 1;
+                        i += 2;
+                    }
+                    Sequence::Command(len) => i += len,
+                    Sequence::Incomplete => break,
+                }
+            } else {
+                out[ret] = buf[i];
+                ret += 1;
+                i += 1;
+            }
+        }
+        self.partial = buf.split_off(i);
+        match ret {
+            0 if closed => Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "the device closed the connection",
+            )),
+            // only negotiation so far, let the caller try again
+            0 => Err(ErrorKind::WouldBlock.into()),
+            n => Ok(n),
+        }
+    }
+}
+
+impl Write for Telnet {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/**
+ * An ssh session through the system `ssh` client
+ *
+ * `ssh` asks for passwords on the terminal itself, so this relies on keys
+ * or an agent, or the operator typing the password. The enable secret is
+ * still handled by `Session::login`.
+ */
+pub struct Ssh {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl Ssh {
+    pub fn connect(host: &str, username: Option<&str>) -> io::Result<Self> {
+        let (host, port) = host_port(host, "22");
+        let dest = match username {
+            Some(u) => format!("{}@{}", u, host),
+            None => host.to_string(),
+        };
+        let mut child = Command::new("ssh")
+            .args(["-tt", "-p", port, &dest])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(ErrorKind::BrokenPipe)?;
+        let stdout = child.stdout.take().ok_or(ErrorKind::BrokenPipe)?;
+        // Safety: the fd belongs to stdout, which lives as long as self
+        unsafe {
+            let fd = stdout.as_raw_fd();
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+}
+
+impl Read for Ssh {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stdout.read(buf)? {
+            0 => Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "ssh closed the connection",
+            )),
+            n => Ok(n),
+        }
+    }
+}
+
+impl Write for Ssh {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl Drop for Ssh {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Logs in over telnet and sends a compiled config
+pub fn write_telnet(
+    host: &str,
+    creds: &Credentials,
+    timeout: Duration,
+    lines: &[String],
+) -> Result<(), SessionError> {
+    let mut session = Session::new(Telnet::connect(host)?)
+        .timeout(timeout)
+        .line_ending("\r\n");
+    session.login(creds)?;
+    session.run(lines)
+}
+
+/// Logs in over ssh and sends a compiled config
+pub fn write_ssh(
+    host: &str,
+    creds: &Credentials,
+    timeout: Duration,
+    lines: &[String],
+) -> Result<(), SessionError> {
+    let mut session = Session::new(Ssh::connect(host, creds.username.as_deref())?)
+        .timeout(timeout)
+        .line_ending("\r");
+    session.login(creds)?;
+    session.run(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_and_ports() {
+        assert_eq!(host_port("10.0.0.1", "23"), ("10.0.0.1", "23"));
+        assert_eq!(host_port("r1.lab:2323", "23"), ("r1.lab", "2323"));
+        assert_eq!(host_port("2001:db8::1", "22"), ("2001:db8::1", "22"));
+        assert_eq!(host_port("[2001:db8::1]", "22"), ("2001:db8::1", "22"));
+        assert_eq!(host_port("[::1]:2222", "22"), ("::1", "2222"));
+    }
+}
@@ -0,0 +1,361 @@
+use crate::ip::IPFamily;
+use crate::parse::{Conf, Scope, Value};
+use crate::template::{Node, ParamRef};
+use serde_json::{json, Map, Value as Data};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+/// The kind of value a template parameter accepts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamKind {
+    /// Any text, `$name`
+    Simple,
+    /// One of the listed options, `$name{a,b}`
+    Selected(Vec<String>),
+    /// A number in the range, `$name{0..100}`
+    Range(Range<isize>),
+    /// An ip address with mask, `$name{\ip}`
+    IP,
+    /// An ipv6 address with prefix length, `$name{\ipv6}`
+    IPv6,
+    /// Either kind of address, `$name{\ipany}`
+    AnyIP,
+    /// An interface or range of interfaces, `$name{\interface}`
+    Interface,
+    /// Entries of a `! for $name` block
+    List(Vec<Param>),
+}
+
+impl fmt::Display for ParamKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Simple => write!(f, "text"),
+            Self::Selected(l) => write!(f, "one of {}", l.join(",")),
+            Self::Range(r) => write!(f, "number {}..{}", r.start, r.end),
+            Self::IP => write!(f, "ip"),
+            Self::IPv6 => write!(f, "ipv6"),
+            Self::AnyIP => write!(f, "ip or ipv6"),
+            Self::Interface => write!(f, "interface"),
+            Self::List(_) => write!(f, "list"),
+        }
+    }
+}
+
+/// A parameter accepted by a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    pub kind: ParamKind,
+    /// Declared with `?`, only used in `?\` commands, or a list (which can be empty)
+    pub optional: bool,
+    /// Used when no value is given, e.g. `60` for `$ssh.timeout=60{0..120}`
+    pub default: Option<String>,
+    /// Template lines referencing the parameter, counting from 1, with the included
+    /// template they're in if it isn't the main one
+    pub lines: Vec<(Option<String>, usize)>,
+}
+
+impl Param {
+    fn new(name: &str, val: &Value) -> Self {
+        let kind = match val {
+            Value::Simple(..) => ParamKind::Simple,
+            Value::Selected(_, l, _) => ParamKind::Selected(l.clone()),
+            Value::Range(_, r, _) => ParamKind::Range(r.clone()),
+            Value::IP(_, IPFamily::V4, _) => ParamKind::IP,
+            Value::IP(_, IPFamily::V6, _) => ParamKind::IPv6,
+            Value::IP(_, IPFamily::Any, _) => ParamKind::AnyIP,
+            Value::Interface(..) => ParamKind::Interface,
+            Value::List(..) => ParamKind::List(Vec::new()),
+        };
+        Self {
+            name: name.to_string(),
+            kind,
+            optional: val.is_optional(),
+            default: None,
+            lines: Vec::new(),
+        }
+    }
+
+    /// The lines it's used on, e.g. `3` or `common/base.conf:6` for an included template
+    pub fn line_names(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .map(|(file, line)| match file {
+                Some(f) => format!("{}:{}", f, line),
+                None => line.to_string(),
+            })
+            .collect()
+    }
+
+    fn json_schema(&self) -> Data {
+        let mut ret = match &self.kind {
+            // numbers are written as they are, and ranges can be quoted
+            ParamKind::Simple => json!({ "type": ["string", "number"] }),
+            ParamKind::Selected(l) if l.len() == 1 => json!({ "enum": [l[0], true] }),
+            ParamKind::Selected(l) => json!({ "type": "string", "enum": l }),
+            ParamKind::Range(r) => json!({
+                "type": ["integer", "string"],
+                "pattern": "^-?[0-9]+$",
+                "minimum": r.start,
+                "exclusiveMaximum": r.end,
+            }),
+            ParamKind::IP => json!({ "type": "string", "pattern": "^(dhcp|[0-9.]+/[0-9]+)$" }),
+            ParamKind::IPv6 => json!({
+                "type": "string",
+                "pattern": "^(dhcp|[0-9a-fA-F:.]+/[0-9]+)$",
+            }),
+            ParamKind::AnyIP => json!({
+                "type": "string",
+                "pattern": "^(dhcp|[0-9.]+/[0-9]+|[0-9a-fA-F:]+/[0-9]+)$",
+            }),
+            ParamKind::Interface => json!({ "type": "string" }),
+            ParamKind::List(items) => json!({ "type": "array", "items": json_object(items) }),
+        };
+        if let Some(d) = &self.default {
+            ret["default"] = match &self.kind {
+                ParamKind::Range(_) => json!(d.parse::<isize>().ok()),
+                ParamKind::Selected(l) if l.len() == 1 => json!(true),
+                _ => json!(d),
+            };
+        }
+        ret["description"] = json!(format!("line {}", self.line_names().join(", ")));
+        ret
+    }
+}
+
+/**
+ * The schema of a table of parameters, the top level or a list entry
+ *
+ * Values files join nested tables with `.`, so `rip.autosum` is a property
+ * of a `rip` table, unless `rip` is a parameter itself. The dotted name is
+ * accepted too, but required parameters are expected in the table.
+ */
+fn json_object(params: &[Param]) -> Data {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    let mut tables: Vec<(String, Vec<Param>)> = Vec::new();
+    for p in params {
+        properties.insert(p.name.clone(), p.json_schema());
+        let key = match p.name.split_once('.') {
+            Some((table, rest)) if !params.iter().any(|o| o.name == table) => {
+                let mut inner = p.clone();
+                inner.name = rest.to_string();
+                match tables.iter_mut().find(|(t, _)| t == table) {
+                    Some((_, l)) => l.push(inner),
+                    None => tables.push((table.to_string(), vec![inner])),
+                }
+                table
+            }
+            _ => &p.name[..],
+        };
+        if !p.optional && p.default.is_none() && !required.iter().any(|r| r == key) {
+            required.push(key.to_string());
+        }
+    }
+    for (table, inner) in tables {
+        properties.insert(table, json_object(&inner));
+    }
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// Builds a JSON schema for a values file filling the template
+pub fn json_schema(title: &str, params: &[Param]) -> Data {
+    let mut ret = json_object(params);
+    ret["$schema"] = json!("http://json-schema.org/draft-07/schema#");
+    ret["title"] = json!(title);
+    ret
+}
+
+fn add_use(
+    ret: &mut Vec<Param>,
+    name: &str,
+    default: Option<&String>,
+    val: &Value,
+    at: (Option<String>, usize),
+    opt: bool,
+) -> usize {
+    let i = match ret.iter().position(|p| p.name == name) {
+        Some(i) => i,
+        None => {
+            let mut p = Param::new(name, val);
+            p.optional = true;
+            ret.push(p);
+            ret.len() - 1
+        }
+    };
+    // only counts as required if it is used outside of `?\` at least once
+    if !opt {
+        ret[i].optional &= val.is_optional();
+    }
+    if ret[i].default.is_none() {
+        ret[i].default = default.cloned();
+    }
+    if !ret[i].lines.contains(&at) {
+        ret[i].lines.push(at);
+    }
+    i
+}
+
+/**
+ * Joins what the two sides of an `! if` found
+ *
+ * `default` is the side that compiles with the values as they are, so its
+ * parameters stay required or optional as they are there, and anything only
+ * the other side uses is optional. Both sides start from `before`, what was
+ * found ahead of the `! if`. The fields of a list that only one side loops
+ * over are left as that side has them, as they only matter to its entries.
+ */
+fn merge_branches(before: &[Param], default: Vec<Param>, other: Vec<Param>) -> Vec<Param> {
+    let mut ret = default;
+    for o in other {
+        let was = match before.iter().find(|b| b.name == o.name).map(|b| &b.kind) {
+            Some(ParamKind::List(was)) => &was[..],
+            _ => &[],
+        };
+        let p = match ret.iter_mut().find(|p| p.name == o.name) {
+            Some(p) => p,
+            None => {
+                ret.push(Param {
+                    optional: true,
+                    ..o
+                });
+                continue;
+            }
+        };
+        if p.default.is_none() {
+            p.default = o.default;
+        }
+        for l in o.lines {
+            if !p.lines.contains(&l) {
+                p.lines.push(l);
+            }
+        }
+        if let (ParamKind::List(items), ParamKind::List(other)) = (&mut p.kind, o.kind) {
+            // a list's fields are only needed where it's looped over
+            if items[..] == *was {
+                *items = other;
+            } else if other[..] != *was {
+                *items = merge_branches(was, std::mem::take(items), other);
+            }
+        }
+    }
+    ret
+}
+
+/// A use of `$^name`, on its way out to the `! for` it belongs to
+struct UpRef<'a> {
+    r: &'a ParamRef,
+    /// How many `! for`s are left to go
+    left: usize,
+    at: (Option<String>, usize),
+    opt: bool,
+}
+
+/// Records a use of a parameter, or hands a `$^name` out to the `! for` it belongs to
+fn add_ref<'a>(
+    ret: &mut Vec<Param>,
+    vals: &HashMap<String, Value>,
+    r: &'a ParamRef,
+    at: (Option<String>, usize),
+    opt: bool,
+    outer: &mut Vec<UpRef<'a>>,
+) {
+    if r.up > 0 {
+        let left = r.up;
+        outer.push(UpRef { r, left, at, opt });
+    } else if let Some(val) = vals.get(&r.name) {
+        add_use(ret, &r.name, r.default.as_ref(), val, at, opt);
+    }
+}
+
+/// Adds the parameters used by `nodes` to `ret`, passing uses of `$^name` back through `outer`
+fn schema_nodes<'a>(
+    nodes: &'a [Node],
+    vals: &HashMap<String, Value>,
+    ret: &mut Vec<Param>,
+    outer: &mut Vec<UpRef<'a>>,
+) {
+    for node in nodes {
+        match node {
+            Node::Line(l) => {
+                for r in l.params() {
+                    add_ref(ret, vals, r, (l.file.clone(), l.number), l.optional, outer);
+                }
+            }
+            Node::For {
+                line,
+                file,
+                name,
+                body,
+            } => {
+                if let Some(val @ Value::List(proto, _)) = vals.get(name) {
+                    let p = add_use(ret, name, None, val, (file.clone(), *line), false);
+                    let mut items = match &mut ret[p].kind {
+                        ParamKind::List(items) => std::mem::take(items),
+                        _ => Vec::new(),
+                    };
+                    let mut up = Vec::new();
+                    schema_nodes(body, proto, &mut items, &mut up);
+                    ret[p].kind = ParamKind::List(items);
+                    for u in up {
+                        match u.left {
+                            1 => {
+                                if let Some(val) = vals.get(&u.r.name) {
+                                    let default = u.r.default.as_ref();
+                                    add_use(ret, &u.r.name, default, val, u.at, u.opt);
+                                }
+                            }
+                            left => outer.push(UpRef {
+                                left: left - 1,
+                                ..u
+                            }),
+                        }
+                    }
+                }
+            }
+            Node::If {
+                line,
+                file,
+                cond,
+                then,
+                otherwise,
+            } => {
+                add_ref(ret, vals, &cond.param, (file.clone(), *line), true, outer);
+                let mut sides = [ret.clone(), ret.clone()];
+                let mut up = Vec::new();
+                schema_nodes(then, vals, &mut sides[0], &mut up);
+                schema_nodes(otherwise, vals, &mut sides[1], &mut up);
+                let [then, otherwise] = sides;
+                // compile and validate only look at the side the values pick
+                *ret = match Conf::test(cond, *line, file.as_deref(), &Scope::new(vals)) {
+                    Ok(true) => merge_branches(ret, then, otherwise),
+                    _ => merge_branches(ret, otherwise, then),
+                };
+                // the `! for` a `$^name` belongs to can't see which side it's on
+                outer.extend(up.into_iter().map(|u| UpRef { opt: true, ..u }));
+            }
+            Node::Section { body, .. } => schema_nodes(body, vals, ret, outer),
+            Node::Comment(..) => (),
+        }
+    }
+}
+
+/// The parameters used by part of a template, with `vals` as its values
+pub(crate) fn schema_of(nodes: &[Node], vals: &HashMap<String, Value>) -> Vec<Param> {
+    let mut ret = Vec::new();
+    schema_nodes(nodes, vals, &mut ret, &mut Vec::new());
+    ret
+}
+
+impl Conf {
+    /// Lists every parameter the template accepts, in the order they're first used
+    pub fn schema(&self) -> Vec<Param> {
+        schema_of(&self.nodes, &self.vals)
+    }
+}
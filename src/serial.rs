@@ -0,0 +1,79 @@
+use crate::session::{Credentials, Session, SessionError};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+fn speed(baud: u32) -> io::Result<libc::speed_t> {
+    Ok(match baud {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} isn't a supported baud rate", baud),
+            ))
+        }
+    })
+}
+
+/**
+ * Opens a serial device (or pseudo-terminal) as a raw 8N1 line
+ *
+ * Reads give up after 100ms with no data, so a `Session` can keep track of
+ * its own timeout.
+ */
+pub fn open(device: &str, baud: u32) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(device)?;
+    let fd = file.as_raw_fd();
+    let speed = speed(baud)?;
+    // Safety: fd is open for the lifetime of `file`, and tio is fully
+    // initialized by tcgetattr before anything reads it
+    unsafe {
+        let mut tio: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut tio) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut tio);
+        tio.c_cflag |= libc::CLOCAL | libc::CREAD;
+        tio.c_cflag &= !(libc::CSTOPB | libc::CRTSCTS);
+        tio.c_cc[libc::VMIN] = 0;
+        tio.c_cc[libc::VTIME] = 1;
+        if libc::cfsetispeed(&mut tio, speed) != 0 || libc::cfsetospeed(&mut tio, speed) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::tcsetattr(fd, libc::TCSANOW, &tio) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(file)
+}
+
+/**
+ * Sends a compiled config to the console of a device, one line at a time
+ *
+ * A console can have a password, or be left logged in to user exec, so it
+ * logs in and gets to privileged exec first.
+ */
+pub fn write_lines(
+    device: &str,
+    baud: u32,
+    login: &Credentials,
+    timeout: Duration,
+    lines: &[String],
+) -> Result<(), SessionError> {
+    let mut session = Session::new(open(device, baud)?).timeout(timeout);
+    session.login(login)?;
+    session.run(lines)
+}
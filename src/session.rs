@@ -0,0 +1,501 @@
+use crate::parse::Conf;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Messages IOS prints when it doesn't accept a command
+const REJECTED: [&str; 4] = [
+    "% Invalid input",
+    "% Incomplete command",
+    "% Ambiguous command",
+    "% Unknown command",
+];
+
+#[derive(Debug)]
+pub enum SessionError {
+    Io(io::Error),
+    /// The device didn't show a prompt in time, after sending `line` (from 1)
+    Timeout {
+        line: Option<usize>,
+        output: String,
+    },
+    /// The device rejected line `line` (from 1) of the config
+    Rejected {
+        line: usize,
+        command: String,
+        message: String,
+    },
+    /// Couldn't get to privileged exec with the credentials given
+    Login(String),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Timeout { line: None, output } => {
+                write!(f, "no prompt from the device, last output: {:?}", output)
+            }
+            Self::Timeout {
+                line: Some(l),
+                output,
+            } => write!(f, "no prompt after line {}, last output: {:?}", l, output),
+            Self::Rejected {
+                line,
+                command,
+                message,
+            } => write!(f, "line {} `{}` was rejected: {}", line, command, message),
+            Self::Login(m) => write!(f, "couldn't log in: {}", m),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<io::Error> for SessionError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Login details for a device that has already been set up
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: Option<String>,
+    /// Password for the line, or the user if there is a username
+    pub password: Option<String>,
+    /// Enable secret
+    pub secret: Option<String>,
+}
+
+impl Credentials {
+    /**
+     * Fills in anything not already set from the values of a template
+     *
+     * Uses `$username` and `$user.password` for local logins, `$password`
+     * for line passwords, and `$secret` for enable.
+     */
+    pub fn or_from(self, conf: &Conf) -> Self {
+        let username = self.username.or_else(|| conf.get("username"));
+        let password = self.password.or_else(|| match username {
+            Some(_) => conf.get("user.password").or_else(|| conf.get("password")),
+            None => conf.get("password"),
+        });
+        Self {
+            username,
+            password,
+            secret: self.secret.or_else(|| conf.get("secret")),
+        }
+    }
+}
+
+/**
+ * Checks for an IOS prompt, e.g. `Router>`, `Switch(config-if)#`
+ *
+ * Only looks at the last line, and ignores trailing whitespace
+ */
+pub fn is_prompt(line: &str) -> bool {
+    let line = line.trim_end();
+    let body = match line.strip_suffix('#').or_else(|| line.strip_suffix('>')) {
+        Some(b) => b,
+        None => return false,
+    };
+    let (host, mode) = match body.find('(') {
+        Some(i) => (&body[..i], Some(&body[i..])),
+        None => (body, None),
+    };
+    let name_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.';
+    let mode_ok = match mode {
+        None => true,
+        Some(m) => {
+            m.len() > 2
+                && m.starts_with('(')
+                && m.ends_with(')')
+                && m[1..m.len() - 1].chars().all(name_char)
+        }
+    };
+    !host.is_empty() && host.chars().all(name_char) && mode_ok
+}
+
+fn is_login_prompt(line: &str) -> bool {
+    let line = line.trim_end();
+    is_prompt(line) || line.ends_with("Username:") || line.ends_with("Password:")
+}
+
+/**
+ * Sends lines to an IOS cli one at a time, waiting for the prompt in between
+ *
+ * Works over anything that can be read and written, e.g. a serial port or a
+ * telnet connection. Reads that return nothing, or time out, are treated as
+ * "no output yet" until `timeout` runs out.
+ */
+pub struct Session<S: Read + Write> {
+    stream: S,
+    buf: String,
+    timeout: Duration,
+    line_ending: &'static str,
+}
+
+impl<S: Read + Write> Session<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buf: String::new(),
+            timeout: Duration::from_secs(30),
+            line_ending: "\r",
+        }
+    }
+
+    /// How long to wait for a prompt after each line
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// What to send after each line, IOS expects `\r`
+    pub fn line_ending(mut self, line_ending: &'static str) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    fn last_line(&self) -> &str {
+        self.buf.rsplit('\n').next().unwrap_or_default()
+    }
+
+    /**
+     * Reads until the last line matches `done`, returning everything read
+     *
+     * The output is cleared after each call
+     */
+    pub fn read_until(&mut self, done: impl Fn(&str) -> bool) -> Result<String, SessionError> {
+        let start = Instant::now();
+        let mut bytes = [0u8; 256];
+        while !done(self.last_line()) {
+            if start.elapsed() > self.timeout {
+                return Err(SessionError::Timeout {
+                    line: None,
+                    output: std::mem::take(&mut self.buf),
+                });
+            }
+            match self.stream.read(&mut bytes) {
+                Ok(0) => thread::sleep(Duration::from_millis(10)),
+                Ok(n) => self
+                    .buf
+                    .push_str(&String::from_utf8_lossy(&bytes[..n]).replace('\r', "")),
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(std::mem::take(&mut self.buf))
+    }
+
+    /// Reads until the device shows a prompt
+    pub fn wait_prompt(&mut self) -> Result<String, SessionError> {
+        self.read_until(is_prompt)
+    }
+
+    /// Sends a line without waiting for anything
+    pub fn send(&mut self, line: &str) -> Result<(), SessionError> {
+        self.stream.write_all(line.as_bytes())?;
+        self.stream.write_all(self.line_ending.as_bytes())?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Wakes the device up and waits for the first prompt
+    pub fn sync(&mut self) -> Result<String, SessionError> {
+        self.send("")?;
+        self.wait_prompt()
+    }
+
+    /**
+     * Gets from the login prompts to privileged exec (`Router#`)
+     *
+     * Answers `Username:` and `Password:`, and runs `enable` if the device
+     * starts in user exec. Each answer is only tried once, being asked the
+     * same thing again means it was refused.
+     */
+    pub fn login(&mut self, creds: &Credentials) -> Result<(), SessionError> {
+        let mut enabling = false;
+        let mut answered = Vec::new();
+        self.send("")?;
+        loop {
+            let output = self.read_until(is_login_prompt)?;
+            let last = output.rsplit('\n').next().unwrap_or_default().trim_end();
+            let asked = if last.ends_with("Username:") {
+                "username"
+            } else if last.ends_with("Password:") && enabling {
+                "enable secret"
+            } else if last.ends_with("Password:") {
+                "password"
+            } else if last.ends_with('>') {
+                if enabling {
+                    return Err(SessionError::Login("enable was refused".to_string()));
+                }
+                enabling = true;
+                self.send("enable")?;
+                continue;
+            } else {
+                return Ok(());
+            };
+            if answered.contains(&asked) {
+                return Err(SessionError::Login(format!(
+                    "the {} was refused: {:?}",
+                    asked,
+                    output.trim()
+                )));
+            }
+            answered.push(asked);
+            let answer = match asked {
+                "username" => &creds.username,
+                "password" => &creds.password,
+                _ => &creds.secret,
+            };
+            let answer = answer
+                .as_deref()
+                .ok_or_else(|| SessionError::Login(format!("the device asked for a {}", asked)))?;
+            self.send(answer)?;
+        }
+    }
+
+    /// Sends a command, and waits for the prompt, failing if IOS rejects it
+    pub fn command(&mut self, line: &str) -> Result<String, SessionError> {
+        self.send(line)?;
+        let output = self.wait_prompt()?;
+        if let Some(message) = output
+            .lines()
+            .find(|l| REJECTED.iter().any(|r| l.trim_start().starts_with(r)))
+        {
+            return Err(SessionError::Rejected {
+                line: 0,
+                command: line.to_string(),
+                message: message.trim().to_string(),
+            });
+        }
+        Ok(output)
+    }
+
+    /// Sends every line of a compiled config, stopping at the first one that fails
+    pub fn run(&mut self, lines: &[String]) -> Result<(), SessionError> {
+        for (i, l) in lines.iter().enumerate() {
+            if l.trim().is_empty() {
+                continue;
+            }
+            self.command(l).map_err(|e| match e {
+                SessionError::Rejected {
+                    command, message, ..
+                } => SessionError::Rejected {
+                    line: i + 1,
+                    command,
+                    message,
+                },
+                SessionError::Timeout { output, .. } => SessionError::Timeout {
+                    line: Some(i + 1),
+                    output,
+                },
+                e => e,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A device that answers each line it's sent with `reply`
+    struct Fake<F: FnMut(&str) -> String> {
+        reply: F,
+        line: String,
+        output: Vec<u8>,
+        sent: Vec<String>,
+    }
+
+    impl<F: FnMut(&str) -> String> Fake<F> {
+        fn new(reply: F) -> Self {
+            Self {
+                reply,
+                line: String::new(),
+                output: Vec::new(),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl<F: FnMut(&str) -> String> Read for Fake<F> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.output.len());
+            buf[..n].copy_from_slice(&self.output[..n]);
+            self.output.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl<F: FnMut(&str) -> String> Write for Fake<F> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for c in String::from_utf8_lossy(buf).chars() {
+                if c == '\r' {
+                    let line = std::mem::take(&mut self.line);
+                    let reply = (self.reply)(&line);
+                    self.output.extend_from_slice(reply.as_bytes());
+                    self.sent.push(line);
+                } else {
+                    self.line.push(c);
+                }
+            }
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A console with password `cisco` and enable secret `class`
+    fn console() -> impl FnMut(&str) -> String {
+        let mut state = "login";
+        move |line| {
+            let (next, reply) = match (state, line) {
+                ("login", "cisco") => ("exec", "\r\nRouter>"),
+                ("login", _) => ("login", "\r\nPassword:"),
+                ("exec", "enable") => ("enable", "\r\nPassword:"),
+                ("enable", "class") => ("priv", "\r\nRouter#"),
+                ("enable", _) => ("enable", "\r\n% Access denied\r\nPassword:"),
+                ("priv", "bogus") => (
+                    "priv",
+                    "\r\n% Invalid input detected at '^' marker.\r\nRouter#",
+                ),
+                (s @ "priv", _) => (s, "\r\nRouter#"),
+                (s, _) => (s, "\r\nRouter>"),
+            };
+            state = next;
+            reply.to_string()
+        }
+    }
+
+    fn creds(password: &str, secret: &str) -> Credentials {
+        Credentials {
+            username: None,
+            password: Some(password.to_string()),
+            secret: Some(secret.to_string()),
+        }
+    }
+
+    fn session<F: FnMut(&str) -> String>(reply: F) -> Session<Fake<F>> {
+        Session::new(Fake::new(reply)).timeout(Duration::from_millis(100))
+    }
+
+    #[test]
+    fn prompts() {
+        assert!(is_prompt("Router>"));
+        assert!(is_prompt("Switch(config-if)# "));
+        assert!(is_prompt("R-1.lab#"));
+        assert!(!is_prompt("Password:"));
+        assert!(!is_prompt("#"));
+        assert!(!is_prompt("Router()#"));
+        assert!(!is_prompt("a line with spaces#"));
+    }
+
+    #[test]
+    fn logs_in_and_enables() {
+        let mut s = session(console());
+        s.login(&creds("cisco", "class")).unwrap();
+        assert_eq!(s.into_inner().sent, ["", "cisco", "enable", "class"]);
+    }
+
+    #[test]
+    fn refused_answers_are_only_sent_once() {
+        let mut s = session(console());
+        let e = s.login(&creds("nope", "class")).unwrap_err();
+        assert!(matches!(&e, SessionError::Login(m) if m.starts_with("the password was refused")));
+        assert_eq!(s.into_inner().sent, ["", "nope"]);
+
+        let mut s = session(console());
+        let e = s.login(&creds("cisco", "nope")).unwrap_err();
+        assert!(
+            matches!(&e, SessionError::Login(m) if m.starts_with("the enable secret was refused"))
+        );
+        assert_eq!(s.into_inner().sent, ["", "cisco", "enable", "nope"]);
+
+        let e = session(console())
+            .login(&Credentials::default())
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "couldn't log in: the device asked for a password"
+        );
+    }
+
+    #[test]
+    fn stops_at_the_rejected_line() {
+        let mut s = session(console());
+        s.login(&creds("cisco", "class")).unwrap();
+        let lines: Vec<String> = ["hostname R1", "", "bogus", "end"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        match s.run(&lines).unwrap_err() {
+            SessionError::Rejected {
+                line,
+                command,
+                message,
+            } => {
+                assert_eq!((line, &command[..]), (3, "bogus"));
+                assert!(message.starts_with("% Invalid input"));
+            }
+            e => panic!("expected a rejected line, got {}", e),
+        }
+        assert_eq!(s.into_inner().sent.last().unwrap(), "bogus");
+    }
+
+    #[test]
+    fn times_out_without_a_prompt() {
+        let mut s = session(|_| "no prompt here".to_string());
+        match s.run(&["hostname R1".to_string()]).unwrap_err() {
+            SessionError::Timeout { line, output } => {
+                assert_eq!(line, Some(1));
+                assert_eq!(output, "no prompt here");
+            }
+            e => panic!("expected a timeout, got {}", e),
+        }
+    }
+
+    #[test]
+    fn credentials_from_the_template() {
+        let conf = Conf::parse("switch.conf")
+            .unwrap()
+            .set("password", "cisco")
+            .unwrap()
+            .set("secret", "class")
+            .unwrap();
+        let creds = Credentials::default().or_from(&conf);
+        assert_eq!(creds, creds_of(None, "cisco", "class"));
+
+        // a local user logs in with their own password, given options win
+        let conf = conf
+            .set("username", "admin")
+            .unwrap()
+            .set("user.password", "pass")
+            .unwrap();
+        let creds = Credentials::default().or_from(&conf);
+        assert_eq!(creds, creds_of(Some("admin"), "pass", "class"));
+        let given = creds_of(None, "other", "given");
+        assert_eq!(
+            given.or_from(&conf),
+            creds_of(Some("admin"), "other", "given")
+        );
+    }
+
+    fn creds_of(username: Option<&str>, password: &str, secret: &str) -> Credentials {
+        Credentials {
+            username: username.map(str::to_string),
+            ..creds(password, secret)
+        }
+    }
+}
@@ -0,0 +1,751 @@
+use crate::interface::Iface;
+use crate::session::{Credentials, Session, SessionError};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, TcpStream};
+use std::time::Duration;
+
+// telnet commands the server skips, from RFC 854
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+/// Where `serve` is in a telnet command, which can be split across reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Incoming {
+    Data,
+    /// After an `IAC`
+    Iac,
+    /// After `WILL`, `WONT`, `DO` or `DONT`, waiting for the option
+    Option,
+    /// Inside a subnegotiation, up to `IAC SE`
+    Sub,
+    /// After an `IAC` inside a subnegotiation
+    SubIac,
+}
+
+/// The cli mode of a simulated device, which decides the commands it takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Exec,
+    Enable,
+    Config,
+    ConfigIf,
+    ConfigLine,
+    ConfigRouter,
+    DhcpConfig,
+    ConfigVlan,
+}
+
+impl Mode {
+    fn prompt(self) -> &'static str {
+        match self {
+            Self::Exec => ">",
+            Self::Enable => "#",
+            Self::Config => "(config)#",
+            Self::ConfigIf => "(config-if)#",
+            Self::ConfigLine => "(config-line)#",
+            Self::ConfigRouter => "(config-router)#",
+            Self::DhcpConfig => "(dhcp-config)#",
+            Self::ConfigVlan => "(config-vlan)#",
+        }
+    }
+
+    fn commands(self) -> &'static [(&'static str, Action)] {
+        match self {
+            Self::Exec => EXEC,
+            Self::Enable => ENABLE,
+            Self::Config => GLOBAL,
+            Self::ConfigIf => INTERFACE,
+            Self::ConfigLine => LINE,
+            Self::ConfigRouter => ROUTER,
+            Self::DhcpConfig => DHCP,
+            Self::ConfigVlan => VLAN,
+        }
+    }
+
+    fn is_config(self) -> bool {
+        !matches!(self, Self::Exec | Self::Enable)
+    }
+}
+
+/// What a command does, other than being accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    None,
+    Enter(Mode),
+    /// Back to global config
+    Exit,
+    /// Back to privileged exec
+    End,
+    Enable,
+    Disable,
+    Logout,
+    Hostname,
+    Write,
+    ShowRun,
+}
+
+// Command patterns: keywords can be abbreviated like IOS allows, `<num>` is
+// a number, `<ip>` an address, `<iface>` an interface name (one or two
+// words), `<word>` anything, and `<text>` the rest of the line
+const EXEC: &[(&str, Action)] = &[
+    ("enable", Action::Enable),
+    ("exit", Action::Logout),
+    ("logout", Action::Logout),
+    ("show <text>", Action::None),
+    ("ping <text>", Action::None),
+    ("terminal length <num>", Action::None),
+];
+
+const ENABLE: &[(&str, Action)] = &[
+    ("configure terminal", Action::Enter(Mode::Config)),
+    ("enable", Action::None),
+    ("disable", Action::Disable),
+    ("exit", Action::Logout),
+    ("logout", Action::Logout),
+    ("write", Action::Write),
+    ("write memory", Action::Write),
+    ("copy running-config startup-config", Action::Write),
+    ("clear <text>", Action::None),
+    ("show running-config", Action::ShowRun),
+    ("show <text>", Action::None),
+    ("ping <text>", Action::None),
+    ("terminal length <num>", Action::None),
+];
+
+const GLOBAL: &[(&str, Action)] = &[
+    ("enable secret <text>", Action::None),
+    ("enable password <text>", Action::None),
+    ("line console <num>", Action::Enter(Mode::ConfigLine)),
+    ("line vty <num> <num>", Action::Enter(Mode::ConfigLine)),
+    ("line aux <num>", Action::Enter(Mode::ConfigLine)),
+    ("service password-encryption", Action::None),
+    ("hostname <word>", Action::Hostname),
+    ("banner motd <text>", Action::None),
+    ("ip domain-lookup", Action::None),
+    ("ip domain-name <word>", Action::None),
+    ("ip nat inside source static <ip> <ip>", Action::None),
+    ("ip nat inside source list <num> pool <word>", Action::None),
+    (
+        "ip nat inside source list <num> pool <word> overload",
+        Action::None,
+    ),
+    (
+        "ip nat inside source list <num> interface <iface> overload",
+        Action::None,
+    ),
+    ("ip nat pool <word> <ip> <ip> netmask <ip>", Action::None),
+    (
+        "ip nat pool <word> <ip> <ip> prefix-length <num>",
+        Action::None,
+    ),
+    ("access-list <num> permit <ip>", Action::None),
+    ("access-list <num> permit <ip> <ip>", Action::None),
+    ("access-list <num> permit host <ip>", Action::None),
+    ("access-list <num> permit any", Action::None),
+    ("access-list <num> deny <ip>", Action::None),
+    ("access-list <num> deny <ip> <ip>", Action::None),
+    ("access-list <num> deny host <ip>", Action::None),
+    ("access-list <num> deny any", Action::None),
+    ("interface <iface>", Action::Enter(Mode::ConfigIf)),
+    ("ip route <ip> <ip> <ip>", Action::None),
+    ("ip route <ip> <ip> <ip> <num>", Action::None),
+    ("ip route <ip> <ip> <iface>", Action::None),
+    ("ip route <ip> <ip> <iface> <num>", Action::None),
+    ("ip routing", Action::None),
+    ("ipv6 unicast-routing", Action::None),
+    ("ipv6 route <prefix> <ipv6>", Action::None),
+    ("ipv6 route <prefix> <ipv6> <num>", Action::None),
+    ("ipv6 route <prefix> <iface>", Action::None),
+    ("ipv6 route <prefix> <iface> <num>", Action::None),
+    ("router rip", Action::Enter(Mode::ConfigRouter)),
+    ("router ospf <num>", Action::Enter(Mode::ConfigRouter)),
+    ("router eigrp <num>", Action::Enter(Mode::ConfigRouter)),
+    ("ip dhcp excluded-address <ip>", Action::None),
+    ("ip dhcp excluded-address <ip> <ip>", Action::None),
+    ("ip dhcp pool <word>", Action::Enter(Mode::DhcpConfig)),
+    ("username <word> secret <text>", Action::None),
+    ("username <word> password <text>", Action::None),
+    (
+        "username <word> privilege <num> secret <text>",
+        Action::None,
+    ),
+    (
+        "username <word> privilege <num> password <text>",
+        Action::None,
+    ),
+    ("crypto key generate rsa", Action::None),
+    ("crypto key generate rsa modulus <num>", Action::None),
+    ("ip ssh time-out <num>", Action::None),
+    ("ip ssh authentication-retries <num>", Action::None),
+    ("ip ssh version <num>", Action::None),
+    ("vlan <word>", Action::Enter(Mode::ConfigVlan)),
+    ("ip http server", Action::None),
+    ("ip default-gateway <ip>", Action::None),
+    (
+        "mac address-table static <word> vlan <num> interface <iface>",
+        Action::None,
+    ),
+    ("do <text>", Action::None),
+    ("end", Action::End),
+    ("exit", Action::End),
+];
+
+const INTERFACE: &[(&str, Action)] = &[
+    ("description <text>", Action::None),
+    ("encapsulation dot1q <num>", Action::None),
+    ("ip address <ip> <ip>", Action::None),
+    ("ip address <ip> <ip> secondary", Action::None),
+    ("ip address dhcp", Action::None),
+    ("ipv6 address <prefix>", Action::None),
+    ("ipv6 address <prefix> eui-64", Action::None),
+    ("ipv6 address <ipv6> link-local", Action::None),
+    ("ipv6 enable", Action::None),
+    ("clock rate <num>", Action::None),
+    ("clockrate <num>", Action::None),
+    ("ip helper-address <ip>", Action::None),
+    ("ip nat inside", Action::None),
+    ("ip nat outside", Action::None),
+    ("ip ospf cost <num>", Action::None),
+    ("shutdown", Action::None),
+    ("duplex <word>", Action::None),
+    ("speed <word>", Action::None),
+    ("switchport mode access", Action::None),
+    ("switchport mode trunk", Action::None),
+    ("switchport access vlan <num>", Action::None),
+    ("switchport trunk allowed vlan <word>", Action::None),
+    ("switchport trunk allowed vlan add <word>", Action::None),
+    ("switchport port-security", Action::None),
+    ("switchport port-security mac-address <word>", Action::None),
+    ("switchport port-security maximum <num>", Action::None),
+    ("switchport port-security violation <word>", Action::None),
+    ("exit", Action::Exit),
+    ("end", Action::End),
+];
+
+const LINE: &[(&str, Action)] = &[
+    ("password <text>", Action::None),
+    ("login", Action::None),
+    ("login local", Action::None),
+    ("logging synchronous", Action::None),
+    ("transport input <word>", Action::None),
+    ("exec-timeout <num> <num>", Action::None),
+    ("exit", Action::Exit),
+    ("end", Action::End),
+];
+
+const ROUTER: &[(&str, Action)] = &[
+    ("version <num>", Action::None),
+    ("auto-summary", Action::None),
+    ("passive-interface <iface>", Action::None),
+    ("passive-interface default", Action::None),
+    ("network <ip>", Action::None),
+    ("network <ip> <ip> area <num>", Action::None),
+    ("default-information originate", Action::None),
+    ("router-id <ip>", Action::None),
+    ("redistribute <text>", Action::None),
+    ("exit", Action::Exit),
+    ("end", Action::End),
+];
+
+const DHCP: &[(&str, Action)] = &[
+    ("network <ip> <ip>", Action::None),
+    ("default-router <ip>", Action::None),
+    ("dns-server <ip>", Action::None),
+    ("domain-name <word>", Action::None),
+    ("lease <num>", Action::None),
+    ("exit", Action::Exit),
+    ("end", Action::End),
+];
+
+const VLAN: &[(&str, Action)] = &[
+    ("name <word>", Action::None),
+    ("exit", Action::Exit),
+    ("end", Action::End),
+];
+
+/// How far a line got through one command pattern
+enum Fit {
+    Full,
+    /// The line ran out before the pattern did
+    Partial,
+    /// The word at this index doesn't fit
+    Bad(usize),
+}
+
+/// The result of looking a line up in a mode's commands
+enum Parsed {
+    Ok(Action),
+    Incomplete,
+    Invalid(usize),
+    Ambiguous(usize),
+}
+
+/// Matches one pattern, also returning the keywords matched as `(word, keyword, exact)`
+fn fit(pattern: &'static str, words: &[(usize, &str)]) -> (Fit, Vec<(usize, &'static str, bool)>) {
+    let mut keywords = Vec::new();
+    let mut w = 0;
+    for tok in pattern.split_whitespace() {
+        if w == words.len() {
+            return (Fit::Partial, keywords);
+        }
+        let word = words[w].1;
+        let ok = match tok {
+            "<text>" => return (Fit::Full, keywords),
+            "<word>" => true,
+            "<num>" => word.parse::<u32>().is_ok(),
+            "<ip>" => word.parse::<Ipv4Addr>().is_ok(),
+            "<ipv6>" => word.parse::<Ipv6Addr>().is_ok(),
+            // an ipv6 address with its prefix length, e.g. `2001:db8::1/64`
+            "<prefix>" => word.split_once('/').is_some_and(|(ip, len)| {
+                ip.parse::<Ipv6Addr>().is_ok() && len.parse::<u8>().is_ok_and(|l| l <= 128)
+            }),
+            "<iface>" => {
+                // `f0/1`, `FastEthernet 0/1` or `range f0/1-2`
+                let mut n = if word.eq_ignore_ascii_case("range") {
+                    2
+                } else {
+                    1
+                };
+                if w + n > words.len() {
+                    return (Fit::Partial, keywords);
+                }
+                if !words[w + n - 1].1.contains(|c: char| c.is_ascii_digit()) {
+                    n += 1;
+                }
+                if w + n > words.len() {
+                    return (Fit::Partial, keywords);
+                }
+                let name: Vec<_> = words[w..w + n].iter().map(|(_, s)| *s).collect();
+                let name = name.join(" ");
+                if Iface::parse(name.trim_start_matches("range ")).is_err() {
+                    return (Fit::Bad(w), keywords);
+                }
+                w += n - 1;
+                true
+            }
+            kw if kw.eq_ignore_ascii_case(word) => {
+                keywords.push((w, kw, true));
+                true
+            }
+            kw if kw.starts_with(&word.to_ascii_lowercase()[..]) => {
+                keywords.push((w, kw, false));
+                true
+            }
+            _ => false,
+        };
+        if !ok {
+            return (Fit::Bad(w), keywords);
+        }
+        w += 1;
+    }
+    if w < words.len() {
+        (Fit::Bad(w), keywords)
+    } else {
+        (Fit::Full, keywords)
+    }
+}
+
+/**
+ * Looks a line up in a list of commands
+ *
+ * `partial` accepts lines that stop early, which is how `no` commands work
+ */
+fn lookup(commands: &[(&'static str, Action)], words: &[(usize, &str)], partial: bool) -> Parsed {
+    let fits: Vec<_> = commands.iter().map(|(p, a)| (fit(p, words), *a)).collect();
+    let mut found: Vec<_> = fits
+        .iter()
+        .filter(|((f, _), _)| !matches!(f, Fit::Bad(_)))
+        .collect();
+    // an abbreviation is ambiguous if it could be two different keywords,
+    // unless it's also a whole keyword
+    for i in 0..words.len() {
+        let at = |kw: &Vec<(usize, &'static str, bool)>| kw.iter().find(|k| k.0 == i).copied();
+        if found.iter().any(|((_, kw), _)| at(kw).is_some_and(|k| k.2)) {
+            found.retain(|((_, kw), _)| at(kw).is_none_or(|k| k.2));
+        } else {
+            let mut names: Vec<_> = found
+                .iter()
+                .filter_map(|((_, kw), _)| at(kw).map(|k| k.1))
+                .collect();
+            names.dedup();
+            if names.len() > 1 {
+                return Parsed::Ambiguous(i);
+            }
+        }
+    }
+    let done = |f: &Fit| matches!(f, Fit::Full) || (partial && matches!(f, Fit::Partial));
+    if let Some((_, a)) = found.iter().find(|((f, _), _)| done(f)) {
+        return Parsed::Ok(*a);
+    }
+    if !found.is_empty() {
+        return Parsed::Incomplete;
+    }
+    let bad = fits
+        .iter()
+        .filter_map(|((f, _), _)| match f {
+            Fit::Bad(i) => Some(*i),
+            _ => None,
+        })
+        .max()
+        .unwrap_or_default();
+    Parsed::Invalid(bad)
+}
+
+/// Where the device is in logging someone in
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    Username,
+    /// Waiting for the password of a user, or the line password
+    Password(Option<String>),
+    /// Waiting for the enable secret
+    Secret,
+    Cli,
+    Closed,
+}
+
+/**
+ * A simulated IOS cli, for trying configs out without hardware
+ *
+ * Tracks the cli mode and accepts the commands the templates produce,
+ * rejecting anything else with the same messages IOS prints. It reads and
+ * writes like a console, so a `Session` can drive it directly, and
+ * `serve` puts it on a tcp connection for the telnet output.
+ */
+pub struct Device {
+    hostname: String,
+    mode: Mode,
+    state: State,
+    user: Option<(String, String)>,
+    password: Option<String>,
+    secret: Option<String>,
+    failures: usize,
+    input: Vec<u8>,
+    last_cr: bool,
+    output: VecDeque<u8>,
+    config: Vec<String>,
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Self::new("Router")
+    }
+}
+
+impl Device {
+    pub fn new(hostname: &str) -> Self {
+        Self {
+            hostname: hostname.to_string(),
+            mode: Mode::Exec,
+            state: State::Cli,
+            user: None,
+            password: None,
+            secret: None,
+            failures: 0,
+            input: Vec::new(),
+            last_cr: false,
+            output: VecDeque::new(),
+            config: Vec::new(),
+        }
+    }
+
+    /// Asks for a username and password before the cli
+    pub fn user(mut self, name: &str, password: &str) -> Self {
+        self.user = Some((name.to_string(), password.to_string()));
+        self
+    }
+
+    /// Asks for a line password before the cli, if there's no user
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// Asks for a secret on `enable`
+    pub fn secret(mut self, secret: &str) -> Self {
+        self.secret = Some(secret.to_string());
+        self
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// Every config command accepted so far, in order
+    pub fn running_config(&self) -> &[String] {
+        &self.config
+    }
+
+    /// Shows the first prompt, as if someone just connected
+    pub fn connect(&mut self) {
+        self.state = if self.user.is_some() {
+            State::Username
+        } else if self.password.is_some() {
+            State::Password(None)
+        } else {
+            State::Cli
+        };
+        self.mode = Mode::Exec;
+        self.failures = 0;
+        self.print("\r\n");
+        self.show_prompt();
+    }
+
+    /// Logs in if needed, and sends every line of a compiled config
+    pub fn run(&mut self, lines: &[String]) -> Result<(), SessionError> {
+        self.connect();
+        let (username, password) = match &self.user {
+            Some((u, p)) => (Some(u.clone()), Some(p.clone())),
+            None => (None, self.password.clone()),
+        };
+        let login = Credentials {
+            username,
+            password,
+            secret: self.secret.clone(),
+        };
+        // everything the device says is already there, so don't wait long
+        let mut session = Session::new(self).timeout(Duration::from_secs(1));
+        session.login(&login)?;
+        session.run(lines)
+    }
+
+    fn print(&mut self, s: &str) {
+        self.output.extend(s.as_bytes());
+    }
+
+    fn prompt(&self) -> String {
+        format!("{}{}", self.hostname, self.mode.prompt())
+    }
+
+    fn show_prompt(&mut self) {
+        let prompt = match self.state {
+            State::Username => "Username: ".to_string(),
+            State::Password(_) | State::Secret => "Password: ".to_string(),
+            State::Cli => self.prompt(),
+            State::Closed => return,
+        };
+        self.print(&prompt);
+    }
+
+    fn line(&mut self, line: &str) {
+        match std::mem::replace(&mut self.state, State::Cli) {
+            State::Username => {
+                if line.is_empty() {
+                    // nothing to answer yet
+                    self.state = State::Username;
+                    return;
+                }
+                self.print(&format!("{}\r\n", line));
+                self.state = State::Password(Some(line.to_string()));
+            }
+            State::Password(user) => {
+                self.print("\r\n");
+                let ok = match (&user, &self.user) {
+                    (Some(u), Some((name, pass))) => u == name && line == pass,
+                    (None, _) => self.password.as_deref() == Some(line),
+                    _ => false,
+                };
+                if !ok {
+                    self.failures += 1;
+                    self.state = if self.failures >= 3 {
+                        self.print("% Bad passwords\r\n");
+                        State::Closed
+                    } else if user.is_some() {
+                        self.print("% Login invalid\r\n\r\n");
+                        State::Username
+                    } else {
+                        State::Password(None)
+                    };
+                }
+            }
+            State::Secret => {
+                self.print("\r\n");
+                if self.secret.as_deref() == Some(line) {
+                    self.mode = Mode::Enable;
+                } else {
+                    self.print("% Bad secrets\r\n\r\n");
+                }
+            }
+            State::Cli => {
+                self.print(&format!("{}\r\n", line));
+                self.command(line);
+            }
+            State::Closed => self.state = State::Closed,
+        }
+        self.show_prompt();
+    }
+
+    fn command(&mut self, line: &str) {
+        let mut words = Vec::new();
+        let mut start = None;
+        for (i, c) in line.char_indices().chain(Some((line.len(), ' '))) {
+            match (c.is_whitespace(), start) {
+                (true, Some(s)) => {
+                    words.push((s, &line[s..i]));
+                    start = None;
+                }
+                (false, None) => start = Some(i),
+                _ => (),
+            }
+        }
+        if words.is_empty() || words[0].1.starts_with('!') {
+            return;
+        }
+        // `no` takes the same commands, but the values can be left off
+        let negated = self.mode.is_config() && words[0].1.eq_ignore_ascii_case("no");
+        let args = if negated { &words[1..] } else { &words[..] };
+        let mut parsed = lookup(self.mode.commands(), args, negated);
+        let mut mode = self.mode;
+        // config sub-modes fall back to global commands, like IOS does
+        if !matches!(parsed, Parsed::Ok(_)) && self.mode.is_config() && self.mode != Mode::Config {
+            if let Parsed::Ok(a) = lookup(GLOBAL, args, negated) {
+                parsed = Parsed::Ok(a);
+                mode = Mode::Config;
+            }
+        }
+        let offset = |i: usize| self.prompt().len() + args.get(i).map_or(line.len(), |w| w.0);
+        match parsed {
+            Parsed::Ok(action) => {
+                self.mode = mode;
+                if self.mode.is_config() && !matches!(action, Action::Exit | Action::End) {
+                    self.config.push(line.trim().to_string());
+                }
+                self.act(action, args);
+            }
+            Parsed::Incomplete => self.print("% Incomplete command.\r\n\r\n"),
+            Parsed::Invalid(_) if !self.mode.is_config() && args.len() == 1 => {
+                self.print(
+                    "% Unknown command or computer name, or unable to find computer address\r\n",
+                );
+            }
+            Parsed::Invalid(i) => {
+                let caret = format!("{:>width$}\r\n", "^", width = offset(i) + 1);
+                self.print(&caret);
+                self.print("% Invalid input detected at '^' marker.\r\n\r\n");
+            }
+            Parsed::Ambiguous(i) => {
+                let word = args[i].1;
+                self.print(&format!("% Ambiguous command:  \"{}\"\r\n", word));
+            }
+        }
+    }
+
+    fn act(&mut self, action: Action, args: &[(usize, &str)]) {
+        match action {
+            Action::None => (),
+            Action::Enter(m) => {
+                if m == Mode::Config && !self.mode.is_config() {
+                    self.print("Enter configuration commands, one per line.  End with CNTL/Z.\r\n");
+                }
+                self.mode = m;
+            }
+            Action::Exit => self.mode = Mode::Config,
+            Action::End => self.mode = Mode::Enable,
+            Action::Enable => match self.secret {
+                Some(_) => self.state = State::Secret,
+                None => self.mode = Mode::Enable,
+            },
+            Action::Disable => self.mode = Mode::Exec,
+            Action::Logout => self.state = State::Closed,
+            Action::Hostname => {
+                if let Some((_, name)) = args.get(1) {
+                    self.hostname = name.trim_matches('"').to_string();
+                }
+            }
+            Action::Write => self.print("Building configuration...\r\n[OK]\r\n"),
+            Action::ShowRun => {
+                let config = self.config.join("\r\n");
+                self.print(&format!("{}\r\n", config));
+            }
+        }
+    }
+
+    /**
+     * Runs the device on a tcp connection until either side hangs up
+     *
+     * Telnet negotiation from the client is ignored.
+     */
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        self.connect();
+        let mut bytes = [0u8; 256];
+        let mut at = Incoming::Data;
+        loop {
+            let out: Vec<u8> = self.output.drain(..).collect();
+            stream.write_all(&out)?;
+            if self.state == State::Closed {
+                return Ok(());
+            }
+            let n = stream.read(&mut bytes)?;
+            if n == 0 {
+                return Ok(());
+            }
+            for &b in &bytes[..n] {
+                at = match (at, b) {
+                    (Incoming::Data, IAC) => Incoming::Iac,
+                    // an escaped 255 is data
+                    (Incoming::Data, _) | (Incoming::Iac, IAC) => {
+                        self.write_all(&[b])?;
+                        Incoming::Data
+                    }
+                    (Incoming::Iac, WILL..=DONT) => Incoming::Option,
+                    (Incoming::Iac, SB) => Incoming::Sub,
+                    (Incoming::Iac, _) | (Incoming::Option, _) => Incoming::Data,
+                    (Incoming::Sub, IAC) => Incoming::SubIac,
+                    (Incoming::SubIac, SE) => Incoming::Data,
+                    (Incoming::Sub, _) | (Incoming::SubIac, _) => Incoming::Sub,
+                };
+            }
+        }
+    }
+}
+
+impl Write for Device {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            let cr = self.last_cr;
+            self.last_cr = b == b'\r';
+            match b {
+                // `\r\n` is one line ending
+                b'\n' if cr => (),
+                b'\r' | b'\n' => {
+                    let line = String::from_utf8_lossy(&self.input).into_owned();
+                    self.input.clear();
+                    self.line(&line);
+                }
+                _ => self.input.push(b),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for Device {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.output.is_empty() && self.state == State::Closed {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "the device logged out",
+            ));
+        }
+        let mut n = 0;
+        while n < buf.len() {
+            match self.output.pop_front() {
+                Some(b) => buf[n] = b,
+                None => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+}
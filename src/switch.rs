@@ -1,4 +1,5 @@
-fn gen_config(config: &str) {
+#[allow(unused)]
+fn gen_config(_config: &str) {
     // First, read swtich.conf for template
     // second, make substitutions from given config
     // third, repeat certain commands
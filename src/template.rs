@@ -0,0 +1,1116 @@
+use crate::error::{ConfError, ErrorKind};
+use crate::file::File;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A reference to a parameter, e.g. `$ip{\ip}|wildcard` or `?nodns{no}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamRef {
+    /// Loop variables like `@index` keep their `@`
+    pub name: String,
+    /// How many `! for`s out the parameter lives, one for each `^` in `$^name`
+    pub up: usize,
+    /// Written with `?` rather than `$`
+    pub optional: bool,
+    /// What's inside the `{}`, e.g. `\ip`, `0..100` or `inside,outside`
+    pub ty: Option<String>,
+    /// What follows a `=`, e.g. `60` in `$ssh.timeout=60{0..120}`
+    pub default: Option<String>,
+    /// Filters applied in order, e.g. `wildcard` in `$ip|wildcard`
+    ///
+    /// `$ip-mask` is the older way of writing `$ip|mask`. A filter that
+    /// takes a parameter keeps it after a `:`, e.g. `eui64:mac`.
+    pub filters: Vec<String>,
+    /// Column of the `$` or `?`, counting from 1
+    pub column: usize,
+}
+
+impl ParamRef {
+    /// The parameters the filters take, e.g. `mac` in `|eui64:mac`, as plain references
+    pub fn filter_args(&self) -> impl Iterator<Item = ParamRef> + '_ {
+        self.filters.iter().filter_map(move |f| self.filter_arg(f))
+    }
+    /// The parameter one of the filters takes, if it takes one
+    pub fn filter_arg(&self, filter: &str) -> Option<ParamRef> {
+        let (_, arg) = filter.split_once(':')?;
+        let name = arg.trim_start_matches('^');
+        Some(ParamRef {
+            name: name.to_string(),
+            up: arg.len() - name.len(),
+            optional: self.optional,
+            ty: None,
+            default: None,
+            filters: Vec::new(),
+            column: self.column,
+        })
+    }
+}
+
+/// A piece of a template line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Part {
+    /// Copied to the output as written, spacing included
+    Text(String),
+    Param(ParamRef),
+}
+
+/// A line of config, with the parameters picked out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// Line number in the template, counting from 1
+    pub number: usize,
+    pub indent: String,
+    /// Starts with `?\`, and is left out if any parameter it uses isn't set
+    pub optional: bool,
+    pub parts: Vec<Part>,
+    /// Parameters only filters use, from [`ParamRef::filter_args`]
+    pub args: Vec<ParamRef>,
+    /// The included template the line came from, if it isn't the main one
+    pub file: Option<String>,
+}
+
+impl Line {
+    /// An error at `column` of this line, in whichever template it came from
+    pub fn error(&self, kind: ErrorKind, column: usize) -> ConfError {
+        ConfError::new(kind)
+            .at(self.number, column)
+            .in_file(self.file.as_deref())
+    }
+
+    /// The parameters on the line, then the ones its filters take
+    pub fn params(&self) -> impl Iterator<Item = &ParamRef> {
+        self.parts
+            .iter()
+            .filter_map(|p| match p {
+                Part::Param(r) => Some(r),
+                Part::Text(_) => None,
+            })
+            .chain(&self.args)
+    }
+}
+
+/// The test of an `! if`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cond {
+    pub param: ParamRef,
+    /// `== value` (true) or `!= value` (false), otherwise the test is whether it's set
+    pub compare: Option<(bool, String)>,
+}
+
+/**
+ * A parsed template
+ *
+ * Blocks keep the line they start on, and like `Line` the included
+ * template they came from if it isn't the main one.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Line(Line),
+    /// `! for $name` up to `! end for`, repeated for each entry of the list
+    For {
+        line: usize,
+        file: Option<String>,
+        name: String,
+        body: Vec<Node>,
+    },
+    /// `! if` up to `! end if`, with an optional `! else`
+    If {
+        line: usize,
+        file: Option<String>,
+        cond: Cond,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    /// `! section name` up to `! end section`, which a template extending this one can replace
+    Section {
+        line: usize,
+        file: Option<String>,
+        name: String,
+        body: Vec<Node>,
+    },
+    /// A `!` or `#` comment, which isn't part of the output
+    Comment(usize, String),
+}
+
+/// Swaps the body of every section called `name`, returning whether there were any
+fn replace_section(nodes: &mut [Node], name: &str, new: &[Node]) -> bool {
+    let mut found = false;
+    for node in nodes {
+        found |= match node {
+            Node::Section { name: n, body, .. } if n == name => {
+                *body = new.to_vec();
+                true
+            }
+            Node::Section { body, .. } | Node::For { body, .. } => replace_section(body, name, new),
+            Node::If {
+                then, otherwise, ..
+            } => replace_section(then, name, new) | replace_section(otherwise, name, new),
+            Node::Line(_) | Node::Comment(..) => false,
+        };
+    }
+    found
+}
+
+fn name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Whether a name can start with `c`, counting `^` for outer scopes and `@` for loop variables
+fn name_start(c: char) -> bool {
+    name_char(c) || c == '^' || c == '@'
+}
+
+/// Reads a parameter reference starting at the `$` or `?` at `start`
+fn lex_param(text: &str, start: usize, number: usize) -> Result<(ParamRef, usize), ConfError> {
+    let word = text[start..]
+        .split(char::is_whitespace)
+        .next()
+        .unwrap_or_default();
+    let err = || {
+        ConfError::new(ErrorKind::BadName(word.trim_end_matches('"').to_string()))
+            .at(number, start + 1)
+    };
+    let up = text[start + 1..].len() - text[start + 1..].trim_start_matches('^').len();
+    let at = text[start + 1 + up..].starts_with('@') as usize;
+    let rest = &text[start + 1 + up + at..];
+    let mut len = rest.find(|c| !name_char(c)).unwrap_or(rest.len());
+    // a sentence can end in a parameter, e.g. `$hostname.`
+    while rest[..len].ends_with('.') {
+        len -= 1;
+    }
+    if len == 0 {
+        return Err(err());
+    }
+    let name = &text[start + 1 + up..start + 1 + up + at + len];
+    let mut end = start + 1 + up + at + len;
+    let mut filters = Vec::new();
+    let mut ty = None;
+    let mut default = None;
+    // `-filter` straight after the name, then the default, type and any `|filter`s
+    let mut dash = true;
+    loop {
+        let rest = &text[end..];
+        if (dash && rest.starts_with('-')) || rest.starts_with('|') {
+            let rest = &rest[1..];
+            let mut len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(err());
+            }
+            // a parameter for the filter, e.g. `|eui64:mac`
+            if rest[len..].starts_with(':') {
+                let arg = &rest[len + 1..];
+                let up = arg.len() - arg.trim_start_matches('^').len();
+                let mut n = arg[up..].find(|c| !name_char(c)).unwrap_or(arg.len() - up);
+                while arg[up..up + n].ends_with('.') {
+                    n -= 1;
+                }
+                if n == 0 {
+                    return Err(err());
+                }
+                len += 1 + up + n;
+            }
+            filters.push(rest[..len].to_string());
+            end += 1 + len;
+        } else if rest.starts_with('=') && default.is_none() && ty.is_none() {
+            let rest = &rest[1..];
+            let len = rest
+                .find(|c: char| c.is_whitespace() || "{}|\"".contains(c))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(err());
+            }
+            default = Some(rest[..len].to_string());
+            end += 1 + len;
+        } else if rest.starts_with('{') && ty.is_none() {
+            let close = rest.find('}').ok_or_else(err)?;
+            ty = Some(rest[1..close].to_string());
+            end += close + 1;
+        } else {
+            break;
+        }
+        dash = false;
+    }
+    let r = ParamRef {
+        name: name.to_string(),
+        up,
+        optional: text[start..].starts_with('?'),
+        ty,
+        default,
+        filters,
+        column: start + 1,
+    };
+    Ok((r, end))
+}
+
+/**
+ * Reads a `${name}` or `?{name}` reference starting at `start`
+ *
+ * The braces hold anything a plain reference could, e.g. `${ip{\ip}|network}`
+ */
+fn lex_delimited(text: &str, start: usize, number: usize) -> Result<(ParamRef, usize), ConfError> {
+    let mut depth = 0;
+    let close = text[start + 1..].find(|c| {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => (),
+        }
+        depth == 0
+    });
+    let word = &text[start..];
+    let err = || {
+        let word = word.split(char::is_whitespace).next().unwrap_or_default();
+        ConfError::new(ErrorKind::BadName(word.to_string())).at(number, start + 1)
+    };
+    let end = start + 1 + close.ok_or_else(err)? + 1;
+    let inner = format!("${}", &text[start + 2..end - 1]);
+    match lex_param(&inner, 0, number) {
+        Ok((r, len)) if len == inner.len() => Ok((
+            ParamRef {
+                optional: text[start..].starts_with('?'),
+                column: start + 1,
+                ..r
+            },
+            end,
+        )),
+        _ => Err(err()),
+    }
+}
+
+/**
+ * Splits a line into text and parameters
+ *
+ * A plain parameter starts a word (quotes count as the start of a word),
+ * while `${name}` and `?{name}` can go anywhere. `\$` and `\?` are a
+ * literal `$` or `?`, and everything else is kept as is.
+ */
+pub fn lex_line(text: &str, number: usize) -> Result<Line, ConfError> {
+    let body = text.trim_start();
+    let indent = text[..text.len() - body.len()].to_string();
+    let optional = body.starts_with("?\\");
+    let mut i = indent.len() + if optional { 2 } else { 0 };
+    let mut parts = Vec::new();
+    let mut buf = String::new();
+    let mut word_start = true;
+    while let Some(c) = text[i..].chars().next() {
+        let next = text[i + c.len_utf8()..].chars().next();
+        if c == '\\' && matches!(next, Some('$') | Some('?')) {
+            buf.push(next.unwrap_or_default());
+            i += 2;
+            word_start = false;
+        } else if (c == '$' || c == '?') && next == Some('{') {
+            let (r, end) = lex_delimited(text, i, number)?;
+            if !buf.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut buf)));
+            }
+            parts.push(Part::Param(r));
+            i = end;
+            word_start = false;
+        } else if word_start && (c == '$' || (c == '?' && next.is_some_and(name_start))) {
+            let (r, end) = lex_param(text, i, number)?;
+            if !buf.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut buf)));
+            }
+            parts.push(Part::Param(r));
+            i = end;
+            word_start = false;
+        } else {
+            buf.push(c);
+            i += c.len_utf8();
+            word_start = c.is_whitespace() || c == '"';
+        }
+    }
+    if !buf.is_empty() {
+        parts.push(Part::Text(buf));
+    }
+    let args = parts
+        .iter()
+        .filter_map(|p| match p {
+            Part::Param(r) => Some(r.filter_args()),
+            Part::Text(_) => None,
+        })
+        .flatten()
+        .collect();
+    Ok(Line {
+        number,
+        indent,
+        optional,
+        parts,
+        args,
+        file: None,
+    })
+}
+
+/// The text of a `!` or `#` line, or `None` if it's config
+fn comment(line: &str) -> Option<&str> {
+    let line = line.trim();
+    line.strip_prefix('!')
+        .or_else(|| line.strip_prefix('#'))
+        .map(str::trim_start)
+}
+
+/// What ended a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum End {
+    For,
+    /// `! end conf`, which also closes a `! for`
+    Conf,
+    Else,
+    If,
+    Define,
+    Section,
+    /// The end of the template
+    Eof,
+}
+
+/// Parses `$param`, `$param == value` or `$param != value`
+fn parse_cond(c: &str, number: usize, column: usize) -> Result<Cond, ConfError> {
+    let err = || ConfError::new(ErrorKind::BadIf(c.to_string())).at(number, column);
+    let lexed = if c.starts_with("${") || c.starts_with("?{") {
+        lex_delimited(c, 0, number)
+    } else if c.starts_with('$') || c.starts_with('?') {
+        lex_param(c, 0, number)
+    } else {
+        return Err(err());
+    };
+    let (mut param, end) = lexed.map_err(|_| err())?;
+    param.column += column - 1;
+    let rest = c[end..].trim();
+    let compare = if rest.is_empty() {
+        None
+    } else {
+        let (eq, value) = match (rest.strip_prefix("=="), rest.strip_prefix("!=")) {
+            (Some(v), _) => (true, v),
+            (_, Some(v)) => (false, v),
+            _ => return Err(err()),
+        };
+        let value = value.trim().trim_matches('"');
+        if value.is_empty() {
+            return Err(err());
+        }
+        Some((eq, value.to_string()))
+    };
+    Ok(Cond { param, compare })
+}
+
+/**
+ * Reads templates, following `! include` and keeping `! define` snippets
+ *
+ * Snippets are expanded as they're read, so a snippet has to be defined
+ * (or included) before it's used, can't use itself, and is only defined once.
+ */
+#[derive(Default)]
+struct Loader {
+    /// Templates being read, to catch includes that loop
+    stack: Vec<PathBuf>,
+    snippets: HashMap<String, Vec<Node>>,
+    /// The template named by `! extends` in the one being read
+    base: Option<Vec<Node>>,
+    /// Things that are allowed, but probably mistakes
+    warnings: Vec<ConfError>,
+}
+
+impl Loader {
+    /// Reads a template, `name` is what errors call it
+    fn read(&self, path: &Path, name: &str) -> Result<Vec<String>, ConfError> {
+        let io_err = |e: io::Error| ConfError::new(ErrorKind::Io(e.to_string())).file(name);
+        let mut lines = File::read(&path.to_string_lossy()).map_err(io_err)?;
+        let mut v = Vec::new();
+        while let Some(l) = lines.next() {
+            v.push(l.map_err(|e| ConfError {
+                line: Some(lines.line()),
+                ..io_err(e)
+            })?);
+        }
+        Ok(v)
+    }
+
+    /// Parses a whole template, `file` is set for included ones
+    fn parse(
+        &mut self,
+        lines: &[String],
+        dir: &Path,
+        file: Option<&str>,
+    ) -> Result<Vec<Node>, ConfError> {
+        let mut i = 0;
+        let outer = self.base.take();
+        let parsed = self.parse_block(lines, &mut i, dir, file);
+        let base = std::mem::replace(&mut self.base, outer);
+        let (nodes, end) = parsed?;
+        let unbalanced = |m: &str| ConfError::new(ErrorKind::Unbalanced(m.to_string())).at(i, 1);
+        match end {
+            End::Else => return Err(unbalanced("`! else` without an `! if`")),
+            End::If => return Err(unbalanced("`! end if` without an `! if`")),
+            End::Define => return Err(unbalanced("`! end define` without a `! define`")),
+            End::Section => return Err(unbalanced("`! end section` without a `! section`")),
+            End::For => {
+                let e = unbalanced("`! end for` without a `! for` ends the template");
+                self.warnings.push(e.in_file(file));
+            }
+            End::Conf | End::Eof => (),
+        }
+        let mut base = match base {
+            Some(b) => b,
+            None => return Ok(nodes),
+        };
+        // the sections replace the base's, and everything else is just comments
+        for node in nodes {
+            match node {
+                Node::Section {
+                    line,
+                    file,
+                    name,
+                    body,
+                } => {
+                    if !replace_section(&mut base, &name, &body) {
+                        let e = ConfError::new(ErrorKind::UnknownSection(name)).at(line, 1);
+                        return Err(e.in_file(file.as_deref()));
+                    }
+                }
+                Node::Line(l) if l.parts.is_empty() => (),
+                Node::Line(l) => return Err(l.error(ErrorKind::NotInSection, 1)),
+                Node::For { line, file, .. } | Node::If { line, file, .. } => {
+                    let e = ConfError::new(ErrorKind::NotInSection).at(line, 1);
+                    return Err(e.in_file(file.as_deref()));
+                }
+                Node::Comment(..) => (),
+            }
+        }
+        Ok(base)
+    }
+
+    fn include(&mut self, dir: &Path, name: &str, number: usize) -> Result<Vec<Node>, ConfError> {
+        let path = dir.join(name);
+        let at = |kind| ConfError::new(kind).at(number, 1);
+        let full = path
+            .canonicalize()
+            .map_err(|e| at(ErrorKind::Io(format!("can't include {}: {}", name, e))))?;
+        if self.stack.contains(&full) {
+            return Err(at(ErrorKind::IncludeCycle(name.to_string())));
+        }
+        let name = path.to_string_lossy().into_owned();
+        let lines = self.read(&path, &name)?;
+        self.stack.push(full);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let ret = self
+            .parse(&lines, dir, Some(&name))
+            .map_err(|e| e.or_file(&name));
+        self.stack.pop();
+        ret
+    }
+
+    fn parse_block(
+        &mut self,
+        lines: &[String],
+        i: &mut usize,
+        dir: &Path,
+        file: Option<&str>,
+    ) -> Result<(Vec<Node>, End), ConfError> {
+        let mut ret = Vec::new();
+        while *i < lines.len() {
+            let text = &lines[*i];
+            *i += 1;
+            let number = *i;
+            let unbalanced =
+                |m: &str| ConfError::new(ErrorKind::Unbalanced(m.to_string())).at(number, 1);
+            let warn = |kind| ConfError::new(kind).at(number, 1).in_file(file);
+            match comment(text) {
+                Some(c) if c.starts_with("end for") => return Ok((ret, End::For)),
+                Some(c) if c.starts_with("end conf") => return Ok((ret, End::Conf)),
+                Some(c) if c.starts_with("end if") => return Ok((ret, End::If)),
+                Some(c) if c.starts_with("end define") => return Ok((ret, End::Define)),
+                Some(c) if c.starts_with("end section") => return Ok((ret, End::Section)),
+                Some("else") => return Ok((ret, End::Else)),
+                Some(c) if c.split_whitespace().next() == Some("else") => {
+                    return Err(unbalanced(
+                        "`! else` can't have a condition, nest an `! if` in it instead",
+                    ))
+                }
+                Some(c) if c.starts_with("for $") => {
+                    let name = c[5..]
+                        .split_whitespace()
+                        .next()
+                        // a list belongs to the scope it's in, and isn't a loop variable
+                        .filter(|n| !n.starts_with(&['^', '@'][..]))
+                        .ok_or_else(|| ConfError::new(ErrorKind::BadFor).at(number, 1))?
+                        .to_string();
+                    // `! end if` can't close a `! for`
+                    let (body, end) = self.parse_block(lines, i, dir, file)?;
+                    if end == End::Eof {
+                        self.warnings.push(warn(ErrorKind::Unbalanced(
+                            "`! for` is never closed".to_string(),
+                        )));
+                    } else if end != End::For && end != End::Conf {
+                        return Err(unbalanced("`! for` is closed by the wrong `! end`"));
+                    }
+                    ret.push(Node::For {
+                        line: number,
+                        file: file.map(str::to_string),
+                        name,
+                        body,
+                    });
+                    if end == End::Eof {
+                        break;
+                    }
+                }
+                Some(c) if c.starts_with("if ") => {
+                    let expr = c[3..].trim();
+                    let column = text.find(expr).unwrap_or_default() + 1;
+                    let cond = parse_cond(expr, number, column)?;
+                    let (then, mut end) = self.parse_block(lines, i, dir, file)?;
+                    let mut otherwise = Vec::new();
+                    if end == End::Else {
+                        let (body, e) = self.parse_block(lines, i, dir, file)?;
+                        otherwise = body;
+                        end = e;
+                    }
+                    if end != End::If {
+                        return Err(unbalanced("`! if` needs a matching `! end if`"));
+                    }
+                    ret.push(Node::If {
+                        line: number,
+                        file: file.map(str::to_string),
+                        cond,
+                        then,
+                        otherwise,
+                    });
+                }
+                Some(c) if c.starts_with("include ") => {
+                    let name = c[8..].trim();
+                    ret.extend(self.include(dir, name, number)?);
+                }
+                Some(c) if c.starts_with("section ") => {
+                    let name = c[8..].trim().to_string();
+                    let (body, end) = self.parse_block(lines, i, dir, file)?;
+                    if end != End::Section {
+                        return Err(unbalanced("`! section` needs a matching `! end section`"));
+                    }
+                    ret.push(Node::Section {
+                        line: number,
+                        file: file.map(str::to_string),
+                        name,
+                        body,
+                    });
+                }
+                Some(c) if c.starts_with("extends ") => {
+                    if self.base.is_some() {
+                        return Err(unbalanced("a template can only extend one other"));
+                    }
+                    let name = c[8..].trim();
+                    self.base = Some(self.include(dir, name, number)?);
+                }
+                Some(c) if c.starts_with("define ") => {
+                    let name = c[7..].trim().to_string();
+                    let (body, end) = self.parse_block(lines, i, dir, file)?;
+                    if end != End::Define {
+                        return Err(unbalanced("`! define` needs a matching `! end define`"));
+                    }
+                    if self.snippets.contains_key(&name) {
+                        let e = ConfError::new(ErrorKind::SnippetRedefined(name)).at(number, 1);
+                        return Err(e);
+                    }
+                    self.snippets.insert(name, body);
+                }
+                Some(c) if c.starts_with("use ") => {
+                    let name = c[4..].trim();
+                    let body = self.snippets.get(name).ok_or_else(|| {
+                        ConfError::new(ErrorKind::UnknownSnippet(name.to_string())).at(number, 1)
+                    })?;
+                    ret.extend(body.iter().cloned());
+                }
+                Some(c) => {
+                    if c == "for" || c.starts_with("for ") {
+                        self.warnings.push(warn(ErrorKind::BadFor));
+                    }
+                    ret.push(Node::Comment(number, c.to_string()))
+                }
+                None => {
+                    let mut line = lex_line(text, number)?;
+                    line.file = file.map(str::to_string);
+                    ret.push(Node::Line(line));
+                }
+            }
+        }
+        Ok((ret, End::Eof))
+    }
+}
+
+/**
+ * Parses the lines of a template, including files relative to `dir`
+ *
+ * A `! end for` or `! end conf` outside of any `! for` ends the template,
+ * and anything after it is ignored.
+ */
+pub fn parse(lines: &[String], dir: &Path) -> Result<Vec<Node>, ConfError> {
+    Loader::default().parse(lines, dir, None)
+}
+
+/// Reads and parses a template file, with its includes
+pub fn load(file: &str) -> Result<Vec<Node>, ConfError> {
+    load_with_warnings(file).map(|(nodes, _)| nodes)
+}
+
+/**
+ * Reads a template like `load`, also returning anything that looks like a mistake
+ *
+ * That's a `! for` that's never closed or a `! end for` outside of one,
+ * which end the template instead, and a `! for` comment without a `$list`.
+ */
+pub fn load_with_warnings(file: &str) -> Result<(Vec<Node>, Vec<ConfError>), ConfError> {
+    let path = Path::new(file);
+    let mut loader = Loader::default();
+    let lines = loader.read(path, file)?;
+    if let Ok(full) = path.canonicalize() {
+        loader.stack.push(full);
+    }
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let nodes = loader
+        .parse(&lines, dir, None)
+        .map_err(|e| e.or_file(file))?;
+    let warnings = loader
+        .warnings
+        .into_iter()
+        .map(|e| e.or_file(file))
+        .collect();
+    Ok((nodes, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(name: &str, column: usize) -> ParamRef {
+        ParamRef {
+            name: name.to_string(),
+            optional: false,
+            up: 0,
+            ty: None,
+            default: None,
+            filters: Vec::new(),
+            column,
+        }
+    }
+
+    fn text(s: &str) -> Part {
+        Part::Text(s.to_string())
+    }
+
+    #[test]
+    fn params_and_text() {
+        let line = lex_line("    ip address $ip $ip-mask", 4).unwrap();
+        assert_eq!(line.number, 4);
+        assert_eq!(line.indent, "    ");
+        assert!(!line.optional);
+        let mask = ParamRef {
+            filters: vec!["mask".to_string()],
+            ..param("ip", 20)
+        };
+        assert_eq!(
+            line.parts,
+            [
+                text("ip address "),
+                Part::Param(param("ip", 16)),
+                text(" "),
+                Part::Param(mask)
+            ]
+        );
+        // quotes start a word, and a sentence can end in a parameter
+        let line = lex_line("hostname \"$name\". done", 1).unwrap();
+        assert_eq!(
+            line.parts,
+            [
+                text("hostname \""),
+                Part::Param(param("name", 11)),
+                text("\". done")
+            ]
+        );
+        let line = lex_line("banner $a.b.", 1).unwrap();
+        assert_eq!(line.params().next().unwrap().name, "a.b");
+    }
+
+    #[test]
+    fn optional_params() {
+        let line = lex_line("?nodns{no} ip domain-lookup", 2).unwrap();
+        let nodns = ParamRef {
+            optional: true,
+            ty: Some("no".to_string()),
+            ..param("nodns", 1)
+        };
+        assert_eq!(line.parts, [Part::Param(nodns), text(" ip domain-lookup")]);
+        // a `?` that isn't followed by a name is just text
+        assert_eq!(lex_line("show ?", 1).unwrap().parts, [text("show ?")]);
+    }
+
+    #[test]
+    fn types() {
+        let ty = |s: &str| lex_line(s, 1).unwrap().params().next().unwrap().ty.clone();
+        assert_eq!(ty("$ip{\\ip}").as_deref(), Some("\\ip"));
+        assert_eq!(ty("$rate{0..100}").as_deref(), Some("0..100"));
+        assert_eq!(
+            ty("$side{inside,outside}").as_deref(),
+            Some("inside,outside")
+        );
+        assert_eq!(ty("$name"), None);
+        let line = lex_line("$a{1..2}x", 1).unwrap();
+        assert_eq!(line.parts[1], text("x"));
+    }
+
+    #[test]
+    fn filters() {
+        let line = lex_line("access-list 1 permit $ip{\\ip}|network|upper x", 1).unwrap();
+        let r = line.params().next().unwrap();
+        assert_eq!(r.ty.as_deref(), Some("\\ip"));
+        assert_eq!(r.filters, ["network", "upper"]);
+        assert_eq!(line.parts[2], text(" x"));
+        // `-` only straight after the name
+        let r = lex_line("$ip-mask|lower", 1).unwrap();
+        assert_eq!(r.params().next().unwrap().filters, ["mask", "lower"]);
+        assert_eq!(lex_line("$a|b-c", 1).unwrap().parts[1], text("-c"));
+        // a filter's parameter is a plain reference the line uses too
+        let line = lex_line("ipv6 address $lan{\\ipv6}|eui64:^mac.", 1).unwrap();
+        let r = line.params().next().unwrap();
+        assert_eq!(r.filters, ["eui64:^mac"]);
+        let mac = line.params().nth(1).unwrap();
+        assert_eq!((&mac.name[..], mac.up, mac.ty.as_ref()), ("mac", 1, None));
+        assert_eq!(line.parts.last(), Some(&text(".")));
+        assert_eq!(lex_line("$mac|link_local", 1).unwrap().args, []);
+        for bad in [
+            "$ip|",
+            "$ip| x",
+            "$ip|network|",
+            "$ip|eui64:",
+            "$ip|eui64: x",
+        ] {
+            let e = lex_line(bad, 1).unwrap_err();
+            assert!(matches!(e.kind, ErrorKind::BadName(_)), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let line = lex_line("a $t=60{1..121}|upper $b=x $c", 1).unwrap();
+        let refs: Vec<_> = line.params().collect();
+        assert_eq!(refs[0].default.as_deref(), Some("60"));
+        assert_eq!(refs[0].ty.as_deref(), Some("1..121"));
+        assert_eq!(refs[0].filters, ["upper"]);
+        assert_eq!(refs[1].default.as_deref(), Some("x"));
+        assert_eq!(refs[2].default, None);
+        // the default comes before the type
+        assert_eq!(lex_line("$a{1..2}=1", 1).unwrap().parts[1], text("=1"));
+        let e = lex_line("x $a= y", 1).unwrap_err();
+        assert_eq!(e.kind, ErrorKind::BadName("$a=".to_string()));
+    }
+
+    #[test]
+    fn optional_lines_and_escapes() {
+        let line = lex_line("  ?\\ip nat $nat.side", 7).unwrap();
+        assert!(line.optional);
+        assert_eq!(line.indent, "  ");
+        assert_eq!(
+            line.parts,
+            [text("ip nat "), Part::Param(param("nat.side", 12))]
+        );
+        let line = lex_line("cost \\$10 \\?x", 1).unwrap();
+        assert_eq!(line.parts, [text("cost $10 ?x")]);
+        // only at the start of a word
+        assert_eq!(lex_line("a$b", 1).unwrap().parts, [text("a$b")]);
+    }
+
+    #[test]
+    fn error_positions() {
+        let e = lex_line("hostname $ x", 3).unwrap_err();
+        assert_eq!(e.kind, ErrorKind::BadName("$".to_string()));
+        assert_eq!((e.line, e.column), (Some(3), Some(10)));
+        let e = lex_line("  ip $ip- x", 5).unwrap_err();
+        assert_eq!(e.kind, ErrorKind::BadName("$ip-".to_string()));
+        assert_eq!((e.line, e.column), (Some(5), Some(6)));
+        let e = lex_line("x $ip{\\ip", 1).unwrap_err();
+        assert_eq!(e.kind, ErrorKind::BadName("$ip{\\ip".to_string()));
+        assert_eq!(e.column, Some(3));
+        let lines = vec!["a".to_string(), "! for $".to_string()];
+        let e = parse(&lines, Path::new("")).unwrap_err();
+        assert_eq!(e.kind, ErrorKind::BadFor);
+        assert_eq!((e.line, e.column), (Some(2), Some(1)));
+    }
+
+    #[test]
+    fn blocks() {
+        let lines: Vec<String> = [
+            "! a comment",
+            "! for $interface",
+            "interface $iface",
+            "! end for",
+            "# end conf",
+            "ignored",
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+        let nodes = parse(&lines, Path::new("")).unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0], Node::Comment(1, "a comment".to_string()));
+        match &nodes[1] {
+            Node::For {
+                line, name, body, ..
+            } => {
+                assert_eq!((*line, &name[..]), (2, "interface"));
+                assert!(matches!(&body[..], [Node::Line(l)] if l.number == 3));
+            }
+            n => panic!("expected a for, got {:?}", n),
+        }
+    }
+
+    #[test]
+    fn delimited_params() {
+        let line = lex_line("ip domain-name ${host}.example.com", 1).unwrap();
+        assert_eq!(
+            line.parts,
+            [
+                text("ip domain-name "),
+                Part::Param(param("host", 16)),
+                text(".example.com")
+            ]
+        );
+        let line = lex_line("port-${num{1..48}}?{suffix}", 1).unwrap();
+        let num = ParamRef {
+            ty: Some("1..48".to_string()),
+            ..param("num", 6)
+        };
+        let suffix = ParamRef {
+            optional: true,
+            ..param("suffix", 19)
+        };
+        assert_eq!(
+            line.parts,
+            [text("port-"), Part::Param(num), Part::Param(suffix)]
+        );
+        let line = lex_line("${ip-mask{\\ip}}", 1).unwrap();
+        let r = line.params().next().unwrap();
+        assert_eq!(r.filters, ["mask"]);
+        assert_eq!(r.ty.as_deref(), Some("\\ip"));
+        assert_eq!(lex_line("\\${x}", 1).unwrap().parts, [text("${x}")]);
+    }
+
+    #[test]
+    fn delimited_errors() {
+        for (line, word, column) in [
+            ("a ${} b", "${}", 3),
+            ("a ${name b", "${name", 3),
+            ("a x${a b}", "${a", 4),
+            ("${a-} x", "${a-}", 1),
+        ] {
+            let e = lex_line(line, 2).unwrap_err();
+            assert_eq!(e.kind, ErrorKind::BadName(word.to_string()), "{}", line);
+            assert_eq!((e.line, e.column), (Some(2), Some(column)), "{}", line);
+        }
+    }
+
+    fn parse_err(lines: &[&str]) -> ConfError {
+        let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        parse(&lines, Path::new("")).unwrap_err()
+    }
+
+    #[test]
+    fn if_blocks() {
+        let lines: Vec<String> = ["! if $a != x", "a", "! else", "b", "! end if"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        match &parse(&lines, Path::new("")).unwrap()[..] {
+            [Node::If {
+                line: 1,
+                file: None,
+                cond,
+                then,
+                otherwise,
+            }] => {
+                assert_eq!(cond.param, param("a", 6));
+                assert_eq!(cond.compare, Some((false, "x".to_string())));
+                assert!(matches!(&then[..], [Node::Line(l)] if l.number == 2));
+                assert!(matches!(&otherwise[..], [Node::Line(l)] if l.number == 4));
+            }
+            n => panic!("expected an if, got {:?}", n),
+        }
+    }
+
+    #[test]
+    fn if_errors() {
+        let e = parse_err(&["! if hostname", "! end if"]);
+        assert_eq!(e.kind, ErrorKind::BadIf("hostname".to_string()));
+        assert_eq!((e.line, e.column), (Some(1), Some(6)));
+        let e = parse_err(&["! if $a ==", "! end if"]);
+        assert_eq!(e.kind, ErrorKind::BadIf("$a ==".to_string()));
+        let e = parse_err(&["! if $a <= 3", "! end if"]);
+        assert_eq!(e.kind, ErrorKind::BadIf("$a <= 3".to_string()));
+        for (lines, line, message) in [
+            (
+                &["! if $a", "a"][..],
+                1,
+                "`! if` needs a matching `! end if`",
+            ),
+            (&["a", "! else"][..], 2, "`! else` without an `! if`"),
+            (&["! end if"][..], 1, "`! end if` without an `! if`"),
+            (
+                &["! if $a", "! for $b", "! end if", "! end for"][..],
+                2,
+                "`! for` is closed by the wrong `! end`",
+            ),
+            (
+                &["! if $a", "! else if $b", "! end if"][..],
+                2,
+                "`! else` can't have a condition, nest an `! if` in it instead",
+            ),
+        ] {
+            let e = parse_err(lines);
+            assert_eq!(e.kind, ErrorKind::Unbalanced(message.to_string()));
+            assert_eq!(e.line, Some(line), "{}", message);
+        }
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn snippets_from_includes() {
+        let lines = lines(&[
+            "! include include/names.conf",
+            "! use names",
+            "! define twice",
+            "! use names",
+            "! end define",
+            "! use twice",
+        ]);
+        let nodes = parse(&lines, Path::new("tests/data")).unwrap();
+        let used: Vec<_> = nodes
+            .iter()
+            .filter_map(|n| match n {
+                Node::Line(l) => Some((l.file.as_deref(), l.number)),
+                _ => None,
+            })
+            .collect();
+        // snippets keep the file and line they were defined on
+        let names = Some("tests/data/include/names.conf");
+        assert_eq!(used, [(names, 3), (names, 3)]);
+    }
+
+    #[test]
+    fn include_errors() {
+        let e = load("tests/data/cycle-a.conf").unwrap_err();
+        assert_eq!(e.kind, ErrorKind::IncludeCycle("cycle-a.conf".to_string()));
+        assert_eq!(e.file.as_deref(), Some("tests/data/cycle-b.conf"));
+        assert_eq!(e.line, Some(3));
+
+        let e = parse(&lines(&["! include none.conf"]), Path::new("tests/data")).unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::Io(m) if m.starts_with("can't include none.conf")));
+        assert_eq!(e.line, Some(1));
+    }
+
+    #[test]
+    fn snippet_errors() {
+        let e = parse(&lines(&["a", "! use nope"]), Path::new("")).unwrap_err();
+        assert_eq!(e.kind, ErrorKind::UnknownSnippet("nope".to_string()));
+        assert_eq!((e.line, e.column), (Some(2), Some(1)));
+        // only defined once it ends, so it can't use itself either
+        let e = parse(
+            &lines(&["! define a", "! use a", "! end define"]),
+            Path::new(""),
+        );
+        assert_eq!(
+            e.unwrap_err().kind,
+            ErrorKind::UnknownSnippet("a".to_string())
+        );
+
+        let e = parse(
+            &lines(&[
+                "! define a",
+                "x",
+                "! end define",
+                "! define a",
+                "y",
+                "! end define",
+            ]),
+            Path::new(""),
+        )
+        .unwrap_err();
+        assert_eq!(e.kind, ErrorKind::SnippetRedefined("a".to_string()));
+        assert_eq!(e.line, Some(4));
+        let e = parse(&lines(&["! define a", "x"]), Path::new("")).unwrap_err();
+        assert_eq!(
+            e.kind,
+            ErrorKind::Unbalanced("`! define` needs a matching `! end define`".to_string())
+        );
+    }
+
+    /// The config lines of a template, as written
+    fn texts(nodes: &[Node]) -> Vec<String> {
+        let mut ret = Vec::new();
+        for n in nodes {
+            match n {
+                Node::Line(l) => ret.push(
+                    l.parts
+                        .iter()
+                        .map(|p| match p {
+                            Part::Text(t) => t.clone(),
+                            Part::Param(r) => format!("${}", r.name),
+                        })
+                        .collect(),
+                ),
+                Node::Section { body, .. } => ret.extend(texts(body)),
+                _ => (),
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn sections() {
+        let nodes = load("tests/data/extends/ospf.conf").unwrap();
+        assert_eq!(
+            texts(&nodes),
+            [
+                "hostname $hostname",
+                "router ospf $process",
+                "banner motd \"$banner\""
+            ]
+        );
+        // the base's lines keep their file, the new section its own
+        match &nodes[2] {
+            Node::Section {
+                name, file, body, ..
+            } => {
+                assert_eq!(
+                    (&name[..], file.as_deref()),
+                    ("routing", Some("tests/data/extends/base.conf"))
+                );
+                assert!(matches!(&body[..], [Node::Line(l)] if l.file.is_none() && l.number == 5));
+            }
+            n => panic!("expected a section, got {:?}", n),
+        }
+
+        // extending a template that extends another
+        let nodes = load("tests/data/extends/quiet.conf").unwrap();
+        assert_eq!(
+            texts(&nodes),
+            ["hostname $hostname", "router ospf $process"]
+        );
+    }
+
+    #[test]
+    fn section_errors() {
+        let e = load("tests/data/extends/unknown.conf").unwrap_err();
+        assert_eq!(e.kind, ErrorKind::UnknownSection("bogus".to_string()));
+        assert_eq!(e.file.as_deref(), Some("tests/data/extends/unknown.conf"));
+        assert_eq!(e.line, Some(2));
+        let e = load("tests/data/extends/stray.conf").unwrap_err();
+        assert_eq!((e.kind, e.line), (ErrorKind::NotInSection, Some(4)));
+        let e = parse(&lines(&["! section a", "x"]), Path::new("")).unwrap_err();
+        assert_eq!(
+            e.kind,
+            ErrorKind::Unbalanced("`! section` needs a matching `! end section`".to_string())
+        );
+    }
+}
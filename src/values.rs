@@ -0,0 +1,363 @@
+use crate::error::{ConfError, ErrorKind};
+use crate::ip::Subnet;
+use crate::parse::{Conf, Value};
+use serde_json::{json, Value as Data};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Formats a values file can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    pub fn from_path(name: &str) -> Option<Self> {
+        match Path::new(name).extension()?.to_str()? {
+            "toml" => Some(Self::Toml),
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn parse(self, s: &str) -> Result<Data, ValuesError> {
+        match self {
+            Self::Toml => toml::from_str(s).map_err(|e| ValuesError::Format(e.to_string())),
+            Self::Json => serde_json::from_str(s).map_err(|e| ValuesError::Format(e.to_string())),
+            Self::Yaml => serde_yaml::from_str(s).map_err(|e| ValuesError::Format(e.to_string())),
+        }
+    }
+
+    pub fn write(self, data: &Data) -> Result<String, ValuesError> {
+        match self {
+            Self::Toml => toml::to_string(data).map_err(|e| ValuesError::Format(e.to_string())),
+            Self::Json => {
+                serde_json::to_string_pretty(data).map_err(|e| ValuesError::Format(e.to_string()))
+            }
+            Self::Yaml => {
+                serde_yaml::to_string(data).map_err(|e| ValuesError::Format(e.to_string()))
+            }
+        }
+    }
+}
+
+/**
+ * Values for a `! for $subnet` list from `IPv4::plan`
+ *
+ * Each entry has `name`, `hosts`, `network` and `gateway`; the other
+ * addresses can be worked out in the template, e.g. `$network{\ip}|broadcast`.
+ */
+pub fn plan(subnets: &[Subnet]) -> Data {
+    let entries: Vec<_> = subnets
+        .iter()
+        .map(|s| {
+            json!({
+                "name": s.name,
+                "hosts": s.hosts,
+                "network": s.net.to_string(),
+                "gateway": s.gateway().to_string(),
+            })
+        })
+        .collect();
+    json!({ "subnet": entries })
+}
+
+/// Every key that couldn't be applied to a template
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyReport {
+    /// keys that aren't parameters of the template
+    pub unknown: Vec<String>,
+    /// keys given a value the parameter doesn't accept, e.g. an array for a plain parameter
+    pub invalid: Vec<String>,
+    /// required parameters that weren't given a value
+    pub missing: Vec<String>,
+}
+
+impl KeyReport {
+    pub fn is_empty(&self) -> bool {
+        self.unknown.is_empty() && self.invalid.is_empty() && self.missing.is_empty()
+    }
+
+    /// Files an error from the template under the matching heading
+    pub fn add(&mut self, e: ConfError) {
+        let param = e.param.unwrap_or_default();
+        // lines can come from an included template, so say which one
+        let at = match (&e.file, e.line) {
+            (Some(f), Some(l)) => format!(" ({}:{})", f, l),
+            (None, Some(l)) => format!(" (line {})", l),
+            (_, None) => String::new(),
+        };
+        match e.kind {
+            ErrorKind::UnknownParam => self.unknown.push(param),
+            ErrorKind::Missing => self.missing.push(format!("{}{}", param, at)),
+            kind if param.is_empty() => self.invalid.push(format!("{}{}", kind, at)),
+            kind => self.invalid.push(format!("{}: {}{}", param, kind, at)),
+        }
+    }
+}
+
+impl fmt::Display for KeyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for (title, keys) in [
+            ("unknown keys", &self.unknown),
+            ("invalid values", &self.invalid),
+            ("missing required values", &self.missing),
+        ] {
+            if keys.is_empty() {
+                continue;
+            }
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            write!(f, "{}:", title)?;
+            for k in keys {
+                write!(f, "\n    {}", k)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ValuesError {
+    Io(std::io::Error),
+    UnknownFormat(String),
+    Format(String),
+    Keys(KeyReport),
+}
+
+impl fmt::Display for ValuesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::UnknownFormat(name) => write!(
+                f,
+                "can't tell the format of {}, expected .toml, .json or .yaml",
+                name
+            ),
+            Self::Format(e) => write!(f, "{}", e),
+            Self::Keys(r) => write!(f, "{}", r),
+        }
+    }
+}
+
+/// Reads a values file, picking the format from the extension
+pub fn read(name: &str) -> Result<Data, ValuesError> {
+    let format =
+        Format::from_path(name).ok_or_else(|| ValuesError::UnknownFormat(name.to_string()))?;
+    format.parse(&std::fs::read_to_string(name).map_err(ValuesError::Io)?)
+}
+
+fn flatten<'a>(data: &'a Data, prefix: &str, ret: &mut Vec<(String, &'a Data)>) {
+    match data {
+        Data::Object(map) => {
+            for (k, v) in map {
+                let key = format!("{}{}", prefix, k);
+                if v.is_object() {
+                    flatten(v, &format!("{}.", key), ret);
+                } else {
+                    ret.push((key, v));
+                }
+            }
+        }
+        _ => ret.push((prefix.trim_end_matches('.').to_string(), data)),
+    }
+}
+
+fn apply(hash: &mut HashMap<String, Value>, data: &Data, path: &str, report: &mut KeyReport) {
+    let mut keys = Vec::new();
+    flatten(data, "", &mut keys);
+    for (key, val) in keys {
+        let full = format!("{}{}", path, key);
+        let param = match hash.get_mut(&key) {
+            Some(p) => p,
+            None => {
+                report.unknown.push(full);
+                continue;
+            }
+        };
+        match (param, val) {
+            (_, Data::Null) | (_, Data::Bool(false)) => (),
+            (Value::List(proto, entries), Data::Array(items)) => {
+                for item in items {
+                    let mut new = proto.clone();
+                    apply(
+                        &mut new,
+                        item,
+                        &format!("{}[{}].", full, entries.len()),
+                        report,
+                    );
+                    entries.push(new);
+                }
+            }
+            (Value::Selected(s, l, _), Data::Bool(true)) if l.len() == 1 => *s = l[0].clone(),
+            (Value::List(..), _)
+            | (_, Data::Array(_))
+            | (_, Data::Object(_))
+            | (_, Data::Bool(_)) => report.invalid.push(full),
+            (_, Data::String(s)) => {
+                if let Err(e) = Conf::set_value(hash, key, s.clone()) {
+                    report.invalid.push(format!("{}: {}", full, e.kind))
+                }
+            }
+            (_, Data::Number(n)) => {
+                if let Err(e) = Conf::set_value(hash, key, n.to_string()) {
+                    report.invalid.push(format!("{}: {}", full, e.kind))
+                }
+            }
+        }
+    }
+}
+
+impl Conf {
+    /// Applies values without checking for missing parameters, so more can be set afterwards
+    pub fn apply_values(mut self, data: &Data) -> (Self, KeyReport) {
+        let mut report = KeyReport::default();
+        apply(&mut self.vals, data, "", &mut report);
+        (self, report)
+    }
+
+    /**
+     * Fills a template from a values file, reporting every problem at once
+     *
+     * Scalars are passed to `set`, `true` marks a single option parameter as
+     * present, and arrays of tables become entries of a `! for $list`.
+     * Nested tables are joined with `.`, so `[rip]` `passive = [...]` fills
+     * `rip.passive`.
+     */
+    pub fn load_values(self, name: &str) -> Result<Self, ValuesError> {
+        let (conf, mut report) = self.apply_values(&read(name)?);
+        conf.validate().into_iter().for_each(|e| report.add(e));
+        if report.is_empty() {
+            Ok(conf)
+        } else {
+            Err(ValuesError::Keys(report))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TEMPLATE: &str = "tests/data/values.conf";
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(Format::from_path("a/b.toml"), Some(Format::Toml));
+        assert_eq!(Format::from_path("b.json"), Some(Format::Json));
+        assert_eq!(Format::from_path("b.yml"), Some(Format::Yaml));
+        assert_eq!(Format::from_path("b.yaml"), Some(Format::Yaml));
+        assert_eq!(Format::from_path("b.conf"), None);
+        assert_eq!(Format::from_path("toml"), None);
+    }
+
+    #[test]
+    fn formats_agree() {
+        let toml = read("tests/data/values.toml").unwrap();
+        assert_eq!(read("tests/data/values.json").unwrap(), toml);
+        // yaml has a dotted key where the others have a table
+        let (conf, report) = Conf::parse(TEMPLATE).unwrap().apply_values(&toml);
+        assert!(report.is_empty(), "{}", report);
+        let yaml = read("tests/data/values.yaml").unwrap();
+        let (conf2, report) = Conf::parse(TEMPLATE).unwrap().apply_values(&yaml);
+        assert!(report.is_empty(), "{}", report);
+        assert_eq!(conf.compile().unwrap(), conf2.compile().unwrap());
+    }
+
+    #[test]
+    fn nested_tables_are_dotted() {
+        let data = json!({ "a": { "b": 1, "c": { "d": "x" } }, "e": [] });
+        let mut keys = Vec::new();
+        flatten(&data, "", &mut keys);
+        let names: Vec<_> = keys.iter().map(|(k, _)| &k[..]).collect();
+        assert_eq!(names, ["a.b", "a.c.d", "e"]);
+    }
+
+    #[test]
+    fn applies_scalars_lists_and_flags() {
+        let data = json!({
+            "hostname": "R1",
+            "nodns": true,
+            "interface": [{ "iface": "g0/0", "ip": "10.0.0.1/8", "nat": { "side": "outside" } }],
+        });
+        let (conf, report) = Conf::parse(TEMPLATE).unwrap().apply_values(&data);
+        assert!(report.is_empty(), "{}", report);
+        let lines = conf.compile().unwrap();
+        for line in [
+            "hostname R1",
+            "no ip domain-lookup",
+            "ip address 10.0.0.1 255.0.0.0",
+            "ip nat outside",
+        ] {
+            assert!(lines.iter().any(|l| l.trim() == line), "no `{}`", line);
+        }
+    }
+
+    #[test]
+    fn false_and_null_leave_the_default() {
+        let data = json!({ "hostname": "R1", "nodns": false, "interface": null });
+        let (conf, report) = Conf::parse(TEMPLATE).unwrap().apply_values(&data);
+        assert!(report.is_empty(), "{}", report);
+        let lines = conf.compile().unwrap();
+        // an unset `?nodns{no}` is left out along with its space
+        assert_eq!(lines, ["hostname R1", "ip domain-lookup"]);
+    }
+
+    #[test]
+    fn reports_every_key_at_once() {
+        let err = Conf::parse(TEMPLATE)
+            .unwrap()
+            .load_values("tests/data/values-bad.yaml")
+            .err()
+            .unwrap();
+        let report = match err {
+            ValuesError::Keys(r) => r,
+            e => panic!("expected a key report, got {}", e),
+        };
+        assert_eq!(report.unknown, ["bogus", "interface[0].mask"]);
+        assert_eq!(
+            report.invalid,
+            ["interface[0].ip: `300` is not a valid ip part", "nodns"]
+        );
+        assert_eq!(
+            report.missing,
+            ["hostname (line 2)", "interface[0].ip (line 6)"]
+        );
+        assert_eq!(
+            report.to_string(),
+            "unknown keys:
+    bogus
+    interface[0].mask
+invalid values:
+    interface[0].ip: `300` is not a valid ip part
+    nodns
+missing required values:
+    hostname (line 2)
+    interface[0].ip (line 6)"
+        );
+    }
+
+    #[test]
+    fn file_errors() {
+        assert!(matches!(
+            read("tests/data/none.toml"),
+            Err(ValuesError::Io(_))
+        ));
+        assert!(matches!(
+            read("tests/data/values.conf"),
+            Err(ValuesError::UnknownFormat(_))
+        ));
+        assert!(matches!(
+            Format::Json.parse("{ \"a\": "),
+            Err(ValuesError::Format(_))
+        ));
+    }
+}
@@ -0,0 +1,291 @@
+use crate::parse::{Conf, Level, Scope, Value};
+use crate::schema::{self, Param, ParamKind};
+use crate::template::Node;
+use std::collections::HashMap;
+use std::io::{self, BufRead, ErrorKind, Write};
+
+/**
+ * Asks for every parameter of a template, one at a time
+ *
+ * Answers are checked as they're typed, and asked again if they aren't
+ * valid. Only the side of an `! if` that the answers so far pick is asked
+ * for. Reads from any `BufRead`, so it can be scripted as well as used
+ * from a terminal.
+ */
+pub struct Wizard<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Wizard<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+
+    fn ask(&mut self, question: &str) -> io::Result<String> {
+        write!(self.output, "{}", question)?;
+        self.output.flush()?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "ran out of answers",
+            ));
+        }
+        Ok(line.trim().to_string())
+    }
+
+    fn ask_yes(&mut self, question: &str) -> io::Result<bool> {
+        loop {
+            match &self
+                .ask(&format!("{} [y/N]: ", question))?
+                .to_ascii_lowercase()[..]
+            {
+                "y" | "yes" => return Ok(true),
+                "" | "n" | "no" => return Ok(false),
+                _ => writeln!(self.output, "  please answer y or n")?,
+            }
+        }
+    }
+
+    fn prompt(
+        &mut self,
+        p: &Param,
+        path: &str,
+        hash: &mut HashMap<String, Value>,
+    ) -> io::Result<()> {
+        let name = format!("{}{}", path, p.name);
+        let hint = match &p.kind {
+            ParamKind::Simple => String::new(),
+            ParamKind::Selected(l) => format!(" ({})", l.join("/")),
+            ParamKind::Range(r) => format!(" ({}..{})", r.start, r.end),
+            ParamKind::IP => " (a.b.c.d/n)".to_string(),
+            ParamKind::IPv6 => " (e.g. 2001:db8::1/64)".to_string(),
+            ParamKind::AnyIP => " (a.b.c.d/n or e.g. 2001:db8::1/64)".to_string(),
+            ParamKind::Interface => " (e.g. f0/1)".to_string(),
+            // lists are handled by prompt_params, unless the template has no entry for it
+            ParamKind::List(_) => return Err(invalid(format!("{} isn't a list", name))),
+        };
+        if let ParamKind::Selected(l) = &p.kind {
+            // a single option is just on or off, e.g. `?nodns{no}`
+            if l.len() == 1 {
+                if self.ask_yes(&format!("{}{}?", name, hint))? {
+                    Conf::set_value(hash, p.name.clone(), l[0].clone()).map_err(invalid)?;
+                }
+                return Ok(());
+            }
+        }
+        let opt = match &p.default {
+            Some(d) => format!(" [{}]", d),
+            None if p.optional => ", optional".to_string(),
+            None => String::new(),
+        };
+        loop {
+            let answer = self.ask(&format!("{}{}{}: ", name, hint, opt))?;
+            if answer.is_empty() {
+                // the value already holds the default
+                if p.optional || p.default.is_some() {
+                    return Ok(());
+                }
+                writeln!(self.output, "  {} is required", name)?;
+            } else {
+                match Conf::set_value(hash, p.name.clone(), answer) {
+                    Ok(()) => return Ok(()),
+                    Err(e) => writeln!(self.output, "  {}", e)?,
+                }
+            }
+        }
+    }
+
+    fn prompt_params(
+        &mut self,
+        params: &[Param],
+        path: &str,
+        hash: &mut HashMap<String, Value>,
+    ) -> io::Result<()> {
+        for p in params {
+            match (&p.kind, hash.get_mut(&p.name)) {
+                (ParamKind::List(items), Some(Value::List(proto, entries))) => {
+                    let name = format!("{}{}", path, p.name);
+                    let mut question = format!("add a {} entry?", name);
+                    while self.ask_yes(&question)? {
+                        let mut new = proto.clone();
+                        let path = format!("{}[{}].", name, entries.len());
+                        self.prompt_params(items, &path, &mut new)?;
+                        entries.push(new);
+                        question = format!("add another {} entry?", name);
+                    }
+                }
+                _ => self.prompt(p, path, hash)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks for `name` from `params`, unless it's already been asked for
+    fn prompt_name(
+        &mut self,
+        name: &str,
+        params: &[Param],
+        path: &str,
+        hash: &mut HashMap<String, Value>,
+        asked: &mut Vec<String>,
+    ) -> io::Result<()> {
+        if asked.iter().any(|a| a == name) {
+            return Ok(());
+        }
+        asked.push(name.to_string());
+        match params.iter().find(|p| p.name == name) {
+            Some(p) => self.prompt_params(std::slice::from_ref(p), path, hash),
+            None => Ok(()),
+        }
+    }
+
+    /**
+     * Asks for the parameters of `nodes` in the order they're used
+     *
+     * `params` describes them, and `asked` has what this list entry (or the
+     * top level) has already asked for. `outer` holds the tables around this
+     * one with the entry each is, outermost first, and `entry` this table's.
+     */
+    #[allow(clippy::too_many_arguments)]
+    fn prompt_nodes(
+        &mut self,
+        nodes: &[Node],
+        params: &[Param],
+        path: &str,
+        hash: &mut HashMap<String, Value>,
+        entry: Option<(usize, usize)>,
+        outer: &[Level],
+        asked: &mut Vec<String>,
+    ) -> io::Result<()> {
+        for node in nodes {
+            match node {
+                Node::Line(l) => {
+                    for r in l.params().filter(|r| r.up == 0) {
+                        self.prompt_name(&r.name, params, path, hash, asked)?;
+                    }
+                }
+                Node::For { name, body, .. } => {
+                    if asked.contains(name) {
+                        continue;
+                    }
+                    asked.push(name.clone());
+                    let items = match params.iter().find(|p| &p.name == name) {
+                        Some(Param {
+                            kind: ParamKind::List(items),
+                            ..
+                        }) => items,
+                        _ => continue,
+                    };
+                    // `$^name`s are the same for every entry, so they're asked first
+                    let mut up = Vec::new();
+                    up_names(body, 1, &mut up);
+                    for n in up {
+                        self.prompt_name(n, params, path, hash, asked)?;
+                    }
+                    // taken out while its entries are asked, so they can see this table
+                    let (proto, mut entries) = match hash.get_mut(name) {
+                        Some(Value::List(proto, entries)) => {
+                            (proto.clone(), std::mem::take(entries))
+                        }
+                        _ => return Err(invalid(format!("{}{} isn't a list", path, name))),
+                    };
+                    let levels = [outer, &[(&*hash, entry)]].concat();
+                    let full = format!("{}{}", path, name);
+                    let mut question = format!("add a {} entry?", full);
+                    while self.ask_yes(&question)? {
+                        let mut new = proto.clone();
+                        let path = format!("{}[{}].", full, entries.len());
+                        // the one being asked for is the last so far
+                        let n = Some((entries.len(), entries.len() + 1));
+                        let mut entry = Vec::new();
+                        self.prompt_nodes(body, items, &path, &mut new, n, &levels, &mut entry)?;
+                        // then anything only other loops over the list use
+                        let rest: Vec<_> = items
+                            .iter()
+                            .filter(|p| !entry.contains(&p.name))
+                            .cloned()
+                            .collect();
+                        self.prompt_params(&rest, &path, &mut new)?;
+                        entries.push(new);
+                        question = format!("add another {} entry?", full);
+                    }
+                    hash.insert(name.clone(), Value::List(proto, entries));
+                }
+                Node::If {
+                    line,
+                    file,
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    if cond.param.up == 0 {
+                        self.prompt_name(&cond.param.name, params, path, hash, asked)?;
+                    }
+                    let levels = [outer, &[(&*hash, entry)]].concat();
+                    let test = Scope::within(&levels, |scope| {
+                        Conf::test(cond, *line, file.as_deref(), scope)
+                    });
+                    let side = match test {
+                        Ok(true) => then,
+                        Ok(false) => otherwise,
+                        Err(e) => return Err(invalid(e)),
+                    };
+                    // required on this side, unless it's optional everywhere
+                    let mut side_params = schema::schema_of(side, hash);
+                    for p in side_params.iter_mut() {
+                        if let Some(o) = params.iter().find(|o| o.name == p.name) {
+                            p.optional &= o.optional;
+                        }
+                    }
+                    self.prompt_nodes(side, &side_params, path, hash, entry, outer, asked)?;
+                }
+                Node::Section { body, .. } => {
+                    self.prompt_nodes(body, params, path, hash, entry, outer, asked)?
+                }
+                Node::Comment(..) => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks for every parameter the template uses, in the order they're used
+    pub fn run(&mut self, mut conf: Conf) -> io::Result<Conf> {
+        let params = conf.schema();
+        let vals = &mut conf.vals;
+        self.prompt_nodes(&conf.nodes, &params, "", vals, None, &[], &mut Vec::new())?;
+        Ok(conf)
+    }
+}
+
+/// The names of the `$^name`s in `nodes` that reach `up` `! for`s out of them
+fn up_names<'a>(nodes: &'a [Node], up: usize, ret: &mut Vec<&'a str>) {
+    for node in nodes {
+        let refs: Vec<_> = match node {
+            Node::Line(l) => l.params().collect(),
+            Node::If { cond, .. } => vec![&cond.param],
+            _ => Vec::new(),
+        };
+        for r in refs {
+            if r.up == up && !ret.contains(&&r.name[..]) {
+                ret.push(&r.name);
+            }
+        }
+        match node {
+            Node::For { body, .. } => up_names(body, up + 1, ret),
+            Node::If {
+                then, otherwise, ..
+            } => {
+                up_names(then, up, ret);
+                up_names(otherwise, up, ret);
+            }
+            Node::Section { body, .. } => up_names(body, up, ret),
+            Node::Line(_) | Node::Comment(..) => (),
+        }
+    }
+}
+
+fn invalid(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e.to_string())
+}
@@ -0,0 +1,38 @@
+use std::process::{Command, Output};
+
+fn lint(template: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_autonetconf"))
+        .args(["lint", template])
+        .output()
+        .expect("failed to run autonetconf")
+}
+
+#[test]
+fn clean_template() {
+    let out = lint("router.conf");
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "router.conf: ok\n");
+}
+
+#[test]
+fn lists_every_problem() {
+    let out = lint("tests/data/lint/several.conf");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: tests/data/lint/several.conf has problems:
+    tests/data/lint/several.conf:3:1: `?\\` line has no parameters, so it's always kept
+    tests/data/lint/several.conf:4:1: `for` must be followed by a `$list`
+    tests/data/lint/several.conf:6:13: iface: used as `{\\ip}` here, but as `{\\interface}` at line 5
+    tests/data/lint/several.conf:7:1: `! for` is never closed
+"
+    );
+}
+
+#[test]
+fn no_template() {
+    let out = lint("tests/data/lint/none.conf");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("tests/data/lint/none.conf"));
+}
@@ -0,0 +1,63 @@
+use std::process::{Command, Output};
+
+fn plan(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_autonetconf"))
+        .arg("plan")
+        .args(args)
+        .output()
+        .expect("failed to run autonetconf")
+}
+
+#[test]
+fn table_by_default() {
+    let out = plan(&["192.168.0.0/24", "wan=2", "lan=120"]);
+    assert!(out.status.success());
+    let out = String::from_utf8_lossy(&out.stdout);
+    let rows: Vec<Vec<&str>> = out
+        .lines()
+        .map(|l| l.split_whitespace().collect())
+        .collect();
+    assert_eq!(rows[0][..3], ["NAME", "HOSTS", "NETWORK"]);
+    assert_eq!(
+        rows[1][..5],
+        [
+            "lan",
+            "120",
+            "192.168.0.0/25",
+            "255.255.255.128",
+            "192.168.0.1"
+        ]
+    );
+    assert_eq!(rows[2][..3], ["wan", "2", "192.168.0.128/30"]);
+    let table = plan(&["192.168.0.0/24", "wan=2", "lan=120", "--format", "table"]);
+    assert_eq!(String::from_utf8_lossy(&table.stdout), out);
+}
+
+#[test]
+fn values_formats() {
+    let out = plan(&["10.0.0.0/24", "lan=10", "--format", "json"]);
+    assert!(out.status.success());
+    let data: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(
+        data,
+        serde_json::json!({ "subnet": [{
+            "name": "lan",
+            "hosts": 10,
+            "network": "10.0.0.0/28",
+            "gateway": "10.0.0.1/28",
+        }] })
+    );
+    let out = plan(&["10.0.0.0/24", "lan=10", "--format", "xml"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with("error: unknown plan format `xml`\n"));
+}
+
+#[test]
+fn block_full() {
+    let out = plan(&["10.0.0.0/28", "lan=20"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: no room for `lan` (20 hosts): the plan needs 32 addresses, but the block has 16\n"
+    );
+}
@@ -0,0 +1,118 @@
+use autonetconf::remote::Telnet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Reads from `telnet` until `len` bytes have come through, or a few seconds pass
+fn read_bytes(telnet: &mut Telnet, len: usize) -> Vec<u8> {
+    let mut ret = Vec::new();
+    let start = Instant::now();
+    let mut buf = [0u8; 16];
+    while ret.len() < len && start.elapsed() < Duration::from_secs(5) {
+        match telnet.read(&mut buf) {
+            Ok(n) => ret.extend_from_slice(&buf[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+            Err(e) => panic!("read failed: {}", e),
+        }
+    }
+    ret
+}
+
+#[test]
+fn keeps_partial_command_across_timeouts() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let device = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        // an escaped 255, split so the reader times out in the middle of it
+        stream.write_all(&[b'a', b'b', 255]).unwrap();
+        thread::sleep(Duration::from_millis(500));
+        stream.write_all(&[255, b'c']).unwrap();
+        thread::sleep(Duration::from_millis(500));
+    });
+    let mut telnet = Telnet::connect(&addr).unwrap();
+    assert_eq!(read_bytes(&mut telnet, 4), [b'a', b'b', 255, b'c']);
+    device.join().unwrap();
+}
+
+#[test]
+fn answers_negotiation() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let device = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        // WILL ECHO, then DO terminal type split across writes
+        stream.write_all(&[255, 251, 1, b'x', 255, 253]).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        stream.write_all(&[24, b'y']).unwrap();
+        let mut answers = [0u8; 6];
+        stream.read_exact(&mut answers).unwrap();
+        answers
+    });
+    let mut telnet = Telnet::connect(&addr).unwrap();
+    assert_eq!(read_bytes(&mut telnet, 2), b"xy");
+    // DO ECHO and WONT terminal type
+    assert_eq!(device.join().unwrap(), [255, 253, 1, 255, 252, 24]);
+}
+
+#[test]
+fn subnegotiation_and_escapes() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let device = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        // terminal type subnegotiation with an escaped 255 inside, then an escaped 255
+        stream
+            .write_all(&[
+                b'a', 255, 250, 24, 255, 255, 240, 255, 240, b'b', 255, 255, b'c',
+            ])
+            .unwrap();
+        thread::sleep(Duration::from_millis(300));
+    });
+    let mut telnet = Telnet::connect(&addr).unwrap();
+    assert_eq!(read_bytes(&mut telnet, 4), [b'a', b'b', 255, b'c']);
+    device.join().unwrap();
+}
+
+#[test]
+fn hands_out_the_rest_after_the_device_hangs_up() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let device = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"Router#").unwrap();
+    });
+    let mut telnet = Telnet::connect(&addr).unwrap();
+    device.join().unwrap();
+    thread::sleep(Duration::from_millis(100));
+    // a small buffer leaves most of it behind, which is still read after the close
+    let mut got = Vec::new();
+    let mut buf = [0u8; 2];
+    let err = loop {
+        match telnet.read(&mut buf) {
+            Ok(n) => got.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(got, b"Router#");
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn connects_to_ipv6_with_a_port() {
+    let listener = match TcpListener::bind("[::1]:0") {
+        Ok(l) => l,
+        // no IPv6 loopback here
+        Err(_) => return,
+    };
+    let port = listener.local_addr().unwrap().port();
+    let device = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"ok").unwrap();
+    });
+    let mut telnet = Telnet::connect(&format!("[::1]:{}", port)).unwrap();
+    assert_eq!(read_bytes(&mut telnet, 2), b"ok");
+    device.join().unwrap();
+}
@@ -0,0 +1,376 @@
+use std::fs;
+use std::process::{Command, Output};
+
+use autonetconf::parse::Conf;
+use autonetconf::values::ValuesError;
+
+fn render(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_autonetconf"))
+        .arg("render")
+        .args(args)
+        .output()
+        .expect("failed to run autonetconf")
+}
+
+fn golden(template: &str, values: &str, expected: &str) {
+    let out = render(&[template, "--values", values]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let expected = fs::read_to_string(expected).unwrap();
+    assert_eq!(String::from_utf8_lossy(&out.stdout), expected);
+}
+
+/// The lines of `template` filled from `values`, without the blank ones
+fn lines(template: &str, values: &str) -> Vec<String> {
+    let conf = Conf::parse(template).unwrap().load_values(values).unwrap();
+    let mut lines = conf.compile().unwrap();
+    lines.retain(|l| !l.trim().is_empty());
+    lines
+}
+
+fn has(lines: &[String], line: &str) -> bool {
+    lines.iter().any(|l| l.trim() == line)
+}
+
+#[test]
+fn values_then_set() {
+    // later options win over the values file
+    let out = render(&[
+        "tests/data/values.conf",
+        "--values",
+        "tests/data/values.toml",
+        "--set",
+        "hostname=R2",
+    ]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let out = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        out.starts_with("hostname R2\nno ip domain-lookup\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn reports_every_bad_value() {
+    let out = render(&[
+        "tests/data/values.conf",
+        "--values",
+        "tests/data/values-bad.yaml",
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: can't render tests/data/values.conf
+unknown keys:
+    bogus
+    interface[0].mask
+invalid values:
+    interface[0].ip: `300` is not a valid ip part
+    nodns
+missing required values:
+    hostname (line 2)
+    interface[0].ip (line 6)
+"
+    );
+}
+
+#[test]
+fn missing_values_file() {
+    let out = render(&["tests/data/values.conf", "--values", "tests/data/none.toml"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with("error: tests/data/none.toml: "));
+}
+
+#[test]
+fn bad_options_keep_going() {
+    let out = render(&[
+        "tests/data/values.conf",
+        "--set",
+        "nodns=yes",
+        "--set",
+        "bogus=1",
+        "--values",
+        "tests/data/values.toml",
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: can't render tests/data/values.conf
+unknown keys:
+    bogus
+invalid values:
+    nodns: `yes` isn't one of no
+"
+    );
+}
+
+#[test]
+fn template_errors() {
+    let out = render(&["tests/data/bad-type.conf"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: tests/data/bad-type.conf:2:11: name: `\\nope` isn't a valid type\n"
+    );
+}
+
+#[test]
+fn check_only() {
+    let out = render(&[
+        "tests/data/values.conf",
+        "--values",
+        "tests/data/values.toml",
+        "--check",
+    ]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "tests/data/values.conf: ok\n"
+    );
+    let out = render(&["tests/data/values.conf", "--check"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: can't render tests/data/values.conf
+missing required values:
+    hostname (line 2)
+"
+    );
+}
+
+#[test]
+fn params_inside_words() {
+    let args = [
+        "tests/data/delimited.conf",
+        "--set",
+        "host=r1",
+        "--set",
+        "num=4",
+        "--set",
+        "ip=10.0.0.1/24",
+    ];
+    let out = render(&args);
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "ip domain-name r1.example.com
+description port-4
+ip address 10.0.0.1 255.255.255.0
+"
+    );
+    let out = render(&[&args[..], &["--set", "suffix=-up"]].concat());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("description port-4-up\n"));
+    // the type in the braces is still checked
+    let out = render(&[&args[..], &["--set", "num=99"]].concat());
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("num: 99 isn't in 1..48"));
+}
+
+#[test]
+fn loop_variables_and_outer_refs() {
+    let args = ["tests/data/loops.conf", "--values", "tests/data/loops.toml"];
+    let out = render(&args);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "hostname R1
+max 50
+vlan 10
+ name users 1/2
+ description first on R1
+ interface GigabitEthernet 0/1 on R1 vlan 10
+ interface GigabitEthernet 0/2 on R1 vlan 10
+vlan 20
+ name voice 2/2
+ description last
+ interface GigabitEthernet 0/3 on R1 vlan 20
+neighbor 10.0.0.1 limit 50
+"
+    );
+    // `$^limit` keeps the range `limit` was declared with
+    let out = render(&[&args[..], &["--set", "limit=500"]].concat());
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("limit: 500 isn't in 0..100"));
+}
+
+#[test]
+fn nested_list_options() {
+    let out = render(&[
+        "tests/data/loops.conf",
+        "--set",
+        "hostname=R1",
+        "--set",
+        "limit=50",
+        "--list",
+        "vlan",
+        "id=10,name=users",
+        "--list",
+        "vlan:0/port",
+        "iface=g0/1",
+        "--item",
+        "vlan:0/port:0",
+        "iface=g0/2",
+    ]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(String::from_utf8_lossy(&out.stdout)
+        .contains(" interface GigabitEthernet 0/2 on R1 vlan 10\n"));
+    // entries count from 0, so there's no second vlan yet
+    let out = render(&[
+        "tests/data/loops.conf",
+        "--list",
+        "vlan",
+        "id=10,name=users",
+        "--list",
+        "vlan:1/port",
+        "iface=g0/1",
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("vlan: list doesn't have an entry 1\n"));
+}
+
+#[test]
+fn router_golden() {
+    golden(
+        "router.conf",
+        "tests/data/router.toml",
+        "tests/data/router.out",
+    );
+}
+
+#[test]
+fn switch_golden() {
+    golden(
+        "switch.conf",
+        "tests/data/switch.yaml",
+        "tests/data/switch.out",
+    );
+}
+
+#[test]
+fn optional_commands_need_every_param() {
+    let router = lines("router.conf", "tests/data/router.toml");
+    // `?\$rip.autosum{no} auto-summary` can't turn into a bare `auto-summary`
+    assert!(!router.iter().any(|l| l.contains("auto-summary")));
+    // only the second interface has a vlan and clock rate
+    assert_eq!(
+        router
+            .iter()
+            .filter(|l| l.contains("encapsulation"))
+            .count(),
+        1
+    );
+    assert!(has(&router, "encapsulation dot1Q 10"));
+    assert!(has(&router, "clockrate 128000"));
+    assert!(has(&router, "ip nat inside"));
+    assert!(!router.iter().any(|l| l.contains("helper-address")));
+
+    let switch = lines("switch.conf", "tests/data/switch.yaml");
+    assert!(has(&switch, "ip domain-name example.com"));
+    assert!(has(&switch, "ip default-gateway 192.168.1.1"));
+    // only the first vlan's access port has port security
+    assert_eq!(
+        switch
+            .iter()
+            .filter(|l| l.contains("port-security"))
+            .count(),
+        1
+    );
+    assert!(has(&switch, "switchport port-security mac-address sticky"));
+}
+
+#[test]
+fn optional_commands_with_set_params() {
+    let conf = Conf::parse("router.conf")
+        .unwrap()
+        .load_values("tests/data/router.toml")
+        .unwrap()
+        .set("rip.autosum", "no")
+        .unwrap()
+        .add_list_vec("rip.network", vec![("network", "10.0.0.0/8")])
+        .unwrap();
+    let lines = conf.compile().unwrap();
+    assert!(has(&lines, "no auto-summary"));
+    assert!(has(&lines, "network 10.0.0.0"));
+}
+
+#[test]
+fn optional_commands_drop_when_cleared() {
+    let out = render(&[
+        "switch.conf",
+        "--values",
+        "tests/data/switch.yaml",
+        "--set",
+        "domain=",
+    ]);
+    assert!(out.status.success());
+    let out = String::from_utf8_lossy(&out.stdout);
+    assert!(!out.contains("domain-name"));
+    assert!(out.contains("username admin privilege 15 secret s3cret"));
+}
+
+#[test]
+fn router_reports_every_bad_value() {
+    let out = render(&["router.conf", "--values", "tests/data/bad.yaml"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "error: can't render router.conf
+unknown keys:
+    bogus
+invalid values:
+    interface[0].ip: `300` is not a valid ip part
+    rip.passive[1].iface: `nope` isn't a known interface type
+    route[0].distance: 200 isn't in 0..100
+    route[1].ip: mask not provided, expected `/<bits>`
+missing required values:
+    banner (common/base.conf:26)
+    interface[0].description (line 66)
+    interface[0].ip (line 70)
+    route[1].ip (line 96)
+    rip.passive[1].iface (line 108)
+    rip.network[1].network (line 111)
+"
+    );
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn switch_missing_values() {
+    let out = render(&[
+        "switch.conf",
+        "--values",
+        "tests/data/switch.yaml",
+        "--set",
+        "hostname=",
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("missing required values:\n    hostname"));
+
+    let err = Conf::parse("switch.conf")
+        .unwrap()
+        .load_values("tests/data/router.toml")
+        .unwrap_err();
+    match err {
+        ValuesError::Keys(report) => {
+            assert!(report.unknown.contains(&"interface".to_string()));
+            assert!(report.missing.iter().any(|m| m.starts_with("username")));
+        }
+        e => panic!("expected a key report, got {}", e),
+    }
+}
@@ -0,0 +1,159 @@
+use autonetconf::parse::Conf;
+use autonetconf::schema::{json_schema, ParamKind};
+use serde_json::{json, Value as Data};
+use std::process::Command;
+
+fn schema(template: &str) -> Data {
+    json_schema(template, &Conf::parse(template).unwrap().schema())
+}
+
+#[test]
+fn nested_tables() {
+    let router = schema("router.conf");
+    let rip = &router["properties"]["rip"];
+    assert_eq!(rip["type"], "object");
+    assert_eq!(rip["properties"]["autosum"]["enum"][0], "no");
+    assert_eq!(rip["properties"]["network"]["type"], "array");
+    assert_eq!(
+        router["properties"]["ipv6"]["properties"]["routing"]["enum"][0],
+        "ipv6"
+    );
+    // list entries are tables too
+    let interface = &router["properties"]["interface"]["items"];
+    assert_eq!(
+        interface["properties"]["nat"]["properties"]["side"]["type"],
+        "string"
+    );
+    // the dotted names are still accepted
+    assert!(router["properties"]["rip.autosum"].is_object());
+}
+
+#[test]
+fn required_in_tables() {
+    let router = schema("router.conf");
+    let required = router["required"].as_array().unwrap();
+    assert!(required.iter().all(|r| !r.as_str().unwrap().contains('.')));
+    let network = &router["properties"]["rip"]["properties"]["network"]["items"];
+    assert_eq!(network["required"], serde_json::json!(["network"]));
+
+    let switch = schema("switch.conf");
+    assert_eq!(
+        switch["properties"]["user"]["properties"]["password"]["type"][0],
+        "string"
+    );
+    assert!(switch["required"]
+        .as_array()
+        .unwrap()
+        .contains(&Data::from("user")));
+}
+
+#[test]
+fn dotted_when_the_table_is_a_param() {
+    // `access` is an interface, so `access.security` can't go in an `access` table
+    let switch = schema("switch.conf");
+    let vlan = &switch["properties"]["vlan"]["items"]["properties"];
+    assert_eq!(vlan["access"]["type"][0], "string");
+    assert!(vlan["access.security"].is_object());
+    assert!(vlan["access"]["properties"].is_null());
+}
+
+#[test]
+fn lists_params() {
+    let params = Conf::parse("tests/data/values.conf").unwrap().schema();
+    let names: Vec<_> = params
+        .iter()
+        .map(|p| (&p.name[..], p.optional, p.lines.clone()))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            ("hostname", false, vec![(None, 2)]),
+            ("nodns", true, vec![(None, 3)]),
+            ("interface", true, vec![(None, 4)])
+        ]
+    );
+    assert_eq!(params[1].kind, ParamKind::Selected(vec!["no".to_string()]));
+    let items = match &params[2].kind {
+        ParamKind::List(items) => items,
+        k => panic!("expected a list, got {}", k),
+    };
+    assert_eq!(items[0].kind, ParamKind::Interface);
+    assert_eq!(items[1].kind, ParamKind::IP);
+    // only used in a `?\` line
+    assert_eq!((&items[2].name[..], items[2].optional), ("nat.side", true));
+}
+
+#[test]
+fn describe_command() {
+    let out = Command::new(env!("CARGO_BIN_EXE_autonetconf"))
+        .args(["describe", "tests/data/values.conf"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let out = String::from_utf8_lossy(&out.stdout);
+    assert!(out.starts_with("NAME "), "{}", out);
+    assert!(out.contains("\n  nat.side "), "{}", out);
+
+    let out = Command::new(env!("CARGO_BIN_EXE_autonetconf"))
+        .args(["describe", "tests/data/values.conf", "--json"])
+        .output()
+        .unwrap();
+    let schema: Data = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(schema["title"], "tests/data/values.conf");
+    assert_eq!(schema["required"], serde_json::json!(["hostname"]));
+}
+
+#[test]
+fn if_branches() {
+    let conf = Conf::parse("tests/data/if.conf").unwrap();
+    let params = conf.schema();
+    let required: Vec<_> = params
+        .iter()
+        .filter(|p| !p.optional)
+        .map(|p| &p.name[..])
+        .collect();
+    // the `! else` sides compile while the conditions are unset, so they're required
+    assert_eq!(required, ["hostname", "host", "hostip", "iface", "vlan"]);
+    let dns = params.iter().find(|p| p.name == "dns").unwrap();
+    assert!(dns.optional);
+    assert_eq!(dns.kind, ParamKind::IP);
+    // which is what validate asks for
+    let missing = conf.missing();
+    assert_eq!(missing, required);
+    // a list's fields are still needed in each of its entries
+    let helpers = params.iter().find(|p| p.name == "helpers").unwrap();
+    match &helpers.kind {
+        ParamKind::List(items) => assert!(!items[0].optional),
+        k => panic!("expected a list, got {}", k),
+    }
+}
+
+#[test]
+fn if_branches_follow_the_values() {
+    let conf = Conf::parse("tests/data/if.conf")
+        .unwrap()
+        .set("domain", "lab")
+        .unwrap()
+        .set("mode", "trunk")
+        .unwrap();
+    let required: Vec<_> = conf
+        .schema()
+        .into_iter()
+        .filter(|p| !p.optional)
+        .map(|p| p.name)
+        .collect();
+    // `$domain` is used on the side it picked, and has a value
+    assert_eq!(required, ["hostname", "domain", "dns", "iface"]);
+    assert_eq!(conf.missing(), ["hostname", "dns", "iface"]);
+}
+
+#[test]
+fn defaults() {
+    let params = Conf::parse("tests/data/defaults.conf").unwrap().schema();
+    let defaults: Vec<_> = params.iter().map(|p| p.default.as_deref()).collect();
+    assert_eq!(defaults, [Some("router"), Some("60"), Some("2")]);
+    let schema = json_schema("defaults", &params);
+    assert_eq!(schema["required"], json!([]));
+    assert_eq!(schema["properties"]["timeout"]["default"], json!(60));
+    assert_eq!(schema["properties"]["version"]["default"], json!("2"));
+}
@@ -0,0 +1,143 @@
+use autonetconf::serial;
+use autonetconf::session::{Credentials, SessionError};
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Opens a pseudo-terminal, returning the non-blocking master side and the path of the other
+fn pty() -> (File, String) {
+    // Safety: the fd is checked before use, and ptsname's buffer is only read before the next call
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        assert!(fd >= 0, "posix_openpt failed");
+        assert_eq!(libc::grantpt(fd), 0);
+        assert_eq!(libc::unlockpt(fd), 0);
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        assert_eq!(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK), 0);
+        let name = libc::ptsname(fd);
+        assert!(!name.is_null());
+        let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+        (File::from_raw_fd(fd), name)
+    }
+}
+
+/**
+ * A console with password `cisco` and enable secret `class`, until `done` is set
+ *
+ * Knows just enough IOS to log in, change mode and reject `bogus`, and
+ * returns every line it was sent.
+ */
+fn console() -> (String, Arc<AtomicBool>, JoinHandle<Vec<String>>) {
+    let (mut master, path) = pty();
+    let done = Arc::new(AtomicBool::new(false));
+    let stop = done.clone();
+    let handle = thread::spawn(move || {
+        let mut state = "login";
+        let mut line = String::new();
+        let mut sent = Vec::new();
+        let mut bytes = [0u8; 256];
+        while !stop.load(Ordering::SeqCst) {
+            let n = match master.read(&mut bytes) {
+                Ok(n) => n,
+                // nothing yet, or nobody has the other side open
+                Err(e)
+                    if e.kind() == ErrorKind::WouldBlock || e.raw_os_error() == Some(libc::EIO) =>
+                {
+                    thread::sleep(Duration::from_millis(5));
+                    continue;
+                }
+                Err(e) => panic!("console read failed: {}", e),
+            };
+            for &b in &bytes[..n] {
+                if b != b'\r' {
+                    line.push(b as char);
+                    continue;
+                }
+                let (next, message) = match (state, line.trim()) {
+                    ("login", "cisco") => ("exec", ""),
+                    ("exec", "enable") => ("enable", ""),
+                    ("enable", "class") => ("priv", ""),
+                    ("enable", _) => ("enable", "% Access denied\r\n"),
+                    ("priv", "configure terminal") => ("config", ""),
+                    (s, "bogus") => (s, "% Invalid input detected at '^' marker.\r\n"),
+                    (_, "end") => ("priv", ""),
+                    ("config" | "if", l) if l.starts_with("interface") => ("if", ""),
+                    (s, _) => (s, ""),
+                };
+                state = next;
+                let prompt = match state {
+                    "login" | "enable" => "Password: ",
+                    "exec" => "Router>",
+                    "priv" => "Router#",
+                    "config" => "Router(config)#",
+                    _ => "Router(config-if)#",
+                };
+                write!(master, "\r\n{}{}", message, prompt).unwrap();
+                sent.push(std::mem::take(&mut line));
+            }
+        }
+        sent
+    });
+    (path, done, handle)
+}
+
+fn config(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|l| l.to_string()).collect()
+}
+
+fn login(password: &str, secret: &str) -> Credentials {
+    Credentials {
+        username: None,
+        password: Some(password.to_string()),
+        secret: Some(secret.to_string()),
+    }
+}
+
+fn write_lines(creds: &Credentials, lines: &[&str]) -> (Result<(), SessionError>, Vec<String>) {
+    let (path, done, handle) = console();
+    let res = serial::write_lines(&path, 9600, creds, Duration::from_secs(5), &config(lines));
+    done.store(true, Ordering::SeqCst);
+    (res, handle.join().unwrap())
+}
+
+#[test]
+fn logs_in_and_sends_every_line() {
+    let lines = [
+        "configure terminal",
+        "hostname R1",
+        "interface g0/0",
+        "ip address 10.0.0.1 255.0.0.0",
+        "end",
+    ];
+    let (res, sent) = write_lines(&login("cisco", "class"), &lines);
+    res.unwrap();
+    assert_eq!(sent[..4], ["", "cisco", "enable", "class"]);
+    assert_eq!(sent[4..], lines);
+}
+
+#[test]
+fn reports_the_rejected_line() {
+    let (res, sent) = write_lines(
+        &login("cisco", "class"),
+        &["configure terminal", "bogus", "hostname R1"],
+    );
+    match res {
+        Err(SessionError::Rejected { line, command, .. }) => {
+            assert_eq!((line, &command[..]), (2, "bogus"))
+        }
+        r => panic!("expected a rejected line, got {:?}", r),
+    }
+    assert_eq!(sent.last().unwrap(), "bogus");
+}
+
+#[test]
+fn wrong_secret() {
+    let (res, sent) = write_lines(&login("cisco", "nope"), &["configure terminal"]);
+    assert!(matches!(res, Err(SessionError::Login(_))), "{:?}", res);
+    assert_eq!(sent, ["", "cisco", "enable", "nope"]);
+}
@@ -0,0 +1,146 @@
+use autonetconf::parse::Conf;
+use autonetconf::session::SessionError;
+use autonetconf::sim::{Device, Mode};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::thread;
+
+/// The config `template` renders to with the values in `values`
+fn compile(template: &str, values: &str) -> Vec<String> {
+    Conf::parse(template)
+        .unwrap()
+        .load_values(values)
+        .unwrap()
+        .compile()
+        .unwrap()
+}
+
+#[test]
+fn accepts_router_conf() {
+    let lines = compile("router.conf", "tests/data/router.toml");
+    let mut device = Device::new("Router").password("p").secret("s");
+    device.run(&lines).unwrap();
+    assert_eq!(device.hostname(), "R1");
+    assert_eq!(device.mode(), Mode::Enable);
+    let config = device.running_config();
+    for line in [
+        "ip nat inside source list 1 pool P",
+        "encapsulation dot1Q 10",
+        "ip route 192.168.0.0 255.255.0.0 GigabitEthernet 0/0 5",
+    ] {
+        assert!(config.iter().any(|l| l == line), "{} wasn't run", line);
+    }
+}
+
+#[test]
+fn accepts_ipv6() {
+    let lines = compile("router.conf", "tests/data/router6.toml");
+    let mut device = Device::new("Router").password("p").secret("s");
+    device.run(&lines).unwrap();
+    let config = device.running_config();
+    for line in [
+        "ipv6 address 2001:db8:1::1/64",
+        "ipv6 unicast-routing",
+        "ipv6 route 2001:db8::/32 GigabitEthernet 0/0",
+        "ipv6 route ::/0 Serial 0/0/0",
+    ] {
+        assert!(config.iter().any(|l| l == line), "{} wasn't run", line);
+    }
+}
+
+#[test]
+fn accepts_switch_conf() {
+    let lines = compile("switch.conf", "tests/data/switch.yaml");
+    let mut device = Device::new("Switch").password("cisco").secret("class");
+    device.run(&lines).unwrap();
+    assert_eq!(device.hostname(), "S1");
+    let config = device.running_config();
+    for line in [
+        "switchport trunk allowed vlan 20",
+        "switchport port-security mac-address sticky",
+        "mac address-table static 0011.2233.4455 vlan 10 interface FastEthernet 0/1",
+    ] {
+        assert!(config.iter().any(|l| l == line), "{} wasn't run", line);
+    }
+}
+
+#[test]
+fn rejects_bad_line() {
+    let mut lines = compile("router.conf", "tests/data/router.toml");
+    let at = lines.iter().position(|l| l == "hostname \"R1\"").unwrap();
+    lines.insert(at + 1, "ip routing bogus".to_string());
+    let mut device = Device::new("Router").password("p").secret("s");
+    match device.run(&lines) {
+        Err(SessionError::Rejected {
+            line,
+            command,
+            message,
+        }) => {
+            assert_eq!(line, at + 2);
+            assert_eq!(command, "ip routing bogus");
+            assert_eq!(message, "% Invalid input detected at '^' marker.");
+        }
+        res => panic!("expected a rejected line, got {:?}", res),
+    }
+    // everything up to it was run
+    assert_eq!(device.hostname(), "R1");
+}
+
+#[test]
+fn rejects_incomplete_and_unknown() {
+    let lines: Vec<String> = [
+        "enable",
+        "configure terminal",
+        "interface g0/0",
+        "ip address",
+    ]
+    .iter()
+    .map(|l| l.to_string())
+    .collect();
+    let err = Device::default().run(&lines).unwrap_err();
+    assert!(matches!(
+        err,
+        SessionError::Rejected { line: 4, ref message, .. } if message == "% Incomplete command."
+    ));
+
+    let lines = vec!["enable".to_string(), "frobnicate".to_string()];
+    let err = Device::default().run(&lines).unwrap_err();
+    assert!(
+        matches!(err, SessionError::Rejected { line: 2, .. }),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn serve_skips_telnet_commands() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let device = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut device = Device::new("Router");
+        device.serve(stream).unwrap();
+        device
+    });
+    let mut client = TcpStream::connect(addr).unwrap();
+    // terminal type subnegotiation with an escaped 255 in it, then WILL ECHO
+    client
+        .write_all(&[255, 250, 24, 0, b'v', 255, 255, b't', 255, 240])
+        .unwrap();
+    client.write_all(b"enable\r").unwrap();
+    client.write_all(&[255, 251, 1]).unwrap();
+    // a command split across writes
+    client.write_all(b"configure terminal\r").unwrap();
+    client.write_all(&[255]).unwrap();
+    client.flush().unwrap();
+    thread::sleep(std::time::Duration::from_millis(50));
+    client.write_all(&[253, 3]).unwrap();
+    client.write_all(b"hostname R9\rend\r").unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+    let mut out = String::new();
+    client.read_to_string(&mut out).unwrap();
+    let device = device.join().unwrap();
+    assert_eq!(device.hostname(), "R9");
+    assert_eq!(device.mode(), Mode::Enable);
+    assert!(!out.contains('%'), "{}", out);
+}
@@ -0,0 +1,151 @@
+use autonetconf::parse::Conf;
+use autonetconf::wizard::Wizard;
+use std::io::ErrorKind;
+
+const TEMPLATE: &str = "tests/data/wizard.conf";
+
+/// Runs the wizard with `answers`, one per line, returning the config and what it asked
+fn run(answers: &[&str]) -> (std::io::Result<Conf>, String) {
+    run_template(TEMPLATE, answers)
+}
+
+fn run_template(template: &str, answers: &[&str]) -> (std::io::Result<Conf>, String) {
+    let script = answers.join("\n") + "\n";
+    let mut out = Vec::new();
+    let conf = Conf::parse(template).unwrap();
+    let res = Wizard::new(script.as_bytes(), &mut out).run(conf);
+    (res, String::from_utf8(out).unwrap())
+}
+
+fn count(out: &str, s: &str) -> usize {
+    out.matches(s).count()
+}
+
+/// The compiled config, without the lines left empty by unset `?\` commands
+fn lines(conf: Conf) -> Vec<String> {
+    let mut lines = conf.compile().unwrap();
+    lines.retain(|l| !l.is_empty());
+    lines
+}
+
+#[test]
+fn reprompts_on_invalid_input() {
+    let (conf, out) = run(&[
+        "",
+        "R1",
+        "",
+        "500",
+        "60",
+        "",
+        "",
+        "bogus",
+        "g0/1",
+        "10.0.0.1",
+        "10.0.0.1/24",
+    ]);
+    let conf = conf.unwrap();
+    assert_eq!(count(&out, "hostname is required"), 1);
+    assert_eq!(count(&out, "hostname: "), 2);
+    assert!(out.contains("domain, optional: "));
+    assert!(out.contains("timeout: 500 isn't in 1..121"));
+    assert!(out.contains("iface: `bogus` isn't a known interface type"));
+    assert_eq!(count(&out, "ip (a.b.c.d/n): "), 2);
+    assert_eq!(
+        lines(conf),
+        [
+            "hostname R1",
+            "ip ssh time-out 60",
+            "ip domain-lookup",
+            "interface GigabitEthernet 0/1",
+            "    ip address 10.0.0.1 255.255.255.0",
+        ]
+    );
+}
+
+#[test]
+fn list_entries() {
+    let (conf, out) = run(&[
+        "R1",
+        "",
+        "60",
+        "",
+        "y",
+        "x",
+        "10",
+        "staff",
+        "yes",
+        "20",
+        "guest",
+        "",
+        "g0/1",
+        "10.0.0.1/24",
+    ]);
+    assert!(out.contains("add a vlan entry? [y/N]: "));
+    assert_eq!(count(&out, "add another vlan entry? [y/N]: "), 2);
+    assert_eq!(count(&out, "vlan[0].num (1..4095): "), 2);
+    assert!(out.contains("vlan[1].name: "));
+    assert_eq!(
+        lines(conf.unwrap())[3..7],
+        ["vlan 10", "    name staff", "vlan 20", "    name guest"]
+    );
+}
+
+#[test]
+fn single_option_is_yes_or_no() {
+    let (conf, out) = run(&["R1", "", "60", "maybe", "Y", "", "g0/1", "10.0.0.1/24"]);
+    assert!(out.contains("nodns (no)? [y/N]: "));
+    assert_eq!(count(&out, "please answer y or n"), 1);
+    assert!(lines(conf.unwrap()).contains(&"no ip domain-lookup".to_string()));
+
+    // no keeps the default command
+    let (conf, _) = run(&["R1", "", "60", "n", "", "g0/1", "10.0.0.1/24"]);
+    assert!(lines(conf.unwrap()).contains(&"ip domain-lookup".to_string()));
+}
+
+#[test]
+fn runs_out_of_answers() {
+    let (res, _) = run(&["R1", ""]);
+    assert_eq!(res.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn only_asks_the_branch_that_renders() {
+    let (conf, out) = run_template(
+        "tests/data/if.conf",
+        &["R1", "lab", "10.0.0.2/32", "g0/1", "trunk", "", ""],
+    );
+    assert!(out.contains("dns (a.b.c.d/n): "));
+    assert!(!out.contains("host: "), "{}", out);
+    assert!(!out.contains("vlan: "), "{}", out);
+    assert!(out.contains("native (1..4095), optional: "));
+    assert_eq!(
+        lines(conf.unwrap())[..5],
+        [
+            "hostname R1",
+            "ip domain-name lab",
+            "ip name-server 10.0.0.2",
+            "interface GigabitEthernet 0/1",
+            "    switchport mode trunk",
+        ]
+    );
+
+    // without a domain the `! else` side is asked instead
+    let (conf, out) = run_template(
+        "tests/data/if.conf",
+        &["R1", "", "ntp", "10.0.0.5", "g0/1", "access", "10", ""],
+    );
+    assert!(!out.contains("dns"), "{}", out);
+    assert!(!out.contains("native"), "{}", out);
+    assert!(lines(conf.unwrap()).contains(&"    switchport access vlan 10".to_string()));
+}
+
+#[test]
+fn defaults_are_offered() {
+    let (conf, out) = run_template("tests/data/defaults.conf", &["", "30", ""]);
+    assert!(out.contains("name [router]: "));
+    assert!(out.contains("timeout (1..121) [60]: "));
+    assert_eq!(
+        lines(conf.unwrap()),
+        ["hostname router", "ip ssh time-out 30", "ip ssh version 2"]
+    );
+}